@@ -0,0 +1,34 @@
+#version 430 core
+
+in vec2 uv;
+
+out vec4 color;
+
+uniform sampler2D gbuffer_albedo;
+uniform sampler2D gbuffer_normal;
+uniform sampler2D gbuffer_material;
+uniform sampler2D gbuffer_depth;
+uniform mat4 inverse_view_projection;
+
+// from lighting.frag
+vec3 shade(vec3 albedo, vec3 world_position, vec3 normal, vec4 material, bool directional_only);
+vec3 apply_fog(vec3 shaded, vec3 world_position);
+
+// full-screen pass for the directional lights, the point and spot lights are added on top by their light volumes
+void main()
+{
+    float depth = texture(gbuffer_depth, uv).r;
+    // nothing was drawn here, leave the background alone
+    if (depth == 1.0) {
+        discard;
+    }
+
+    vec4 world = inverse_view_projection * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+    vec3 world_position = world.xyz / world.w;
+
+    vec3 albedo = texture(gbuffer_albedo, uv).rgb;
+    vec3 normal = normalize(texture(gbuffer_normal, uv).xyz);
+    vec4 material = texture(gbuffer_material, uv);
+
+    color = vec4(apply_fog(shade(albedo, world_position, normal, material, true), world_position), 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,44 @@
+#version 430 core
+
+out vec4 color;
+
+// the lights are declared in lighting.frag, this is the one the sphere is drawn for
+uniform int light_index;
+
+uniform sampler2D gbuffer_albedo;
+uniform sampler2D gbuffer_normal;
+uniform sampler2D gbuffer_material;
+uniform sampler2D gbuffer_depth;
+uniform mat4 inverse_view_projection;
+uniform vec2 screen_size;
+
+uniform bool fog_enabled;
+uniform vec3 camera_position;
+
+// from lighting.frag
+vec3 indexed_light_contribution(int index, vec3 world_position, vec3 normal, vec4 material);
+float fog_amount(vec3 to_fragment);
+
+// one point or spot light, drawn as a sphere covering its range and added on top of the directional lighting
+void main()
+{
+    vec2 uv = gl_FragCoord.xy / screen_size;
+    float depth = texture(gbuffer_depth, uv).r;
+    if (depth == 1.0) {
+        discard;
+    }
+
+    vec4 world = inverse_view_projection * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+    vec3 world_position = world.xyz / world.w;
+
+    vec3 albedo = texture(gbuffer_albedo, uv).rgb;
+    vec3 normal = normalize(texture(gbuffer_normal, uv).xyz);
+    vec4 material = texture(gbuffer_material, uv);
+
+    vec3 lighting = albedo * indexed_light_contribution(light_index, world_position, normal, material);
+    // the fog was already mixed in by the directional pass, so only the visible part of the light is added
+    if (fog_enabled) {
+        lighting *= 1.0 - fog_amount(world_position - camera_position);
+    }
+    color = vec4(lighting, 1.0);
+}
\ No newline at end of file
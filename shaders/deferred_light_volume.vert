@@ -0,0 +1,11 @@
+#version 430 core
+
+layout(location = 0) in vec3 position;
+
+// view-projection times the placement of the sphere around the light
+layout(location = 3) uniform mat4 transformation_matrix;
+
+void main()
+{
+    gl_Position = transformation_matrix * vec4(position, 1.0f);
+}
\ No newline at end of file
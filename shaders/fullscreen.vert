@@ -0,0 +1,11 @@
+#version 430 core
+
+out vec2 uv;
+
+// one triangle big enough to cover the screen, made without any vertex buffers
+void main()
+{
+    vec2 position = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+    uv = position;
+    gl_Position = vec4(position * 2.0 - 1.0, 0.0, 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,22 @@
+#version 430 core
+
+// the geometry pass of the deferred path, uses simple.vert
+in VS_OUTPUT {
+    vec4 color;
+    vec3 normal;
+    vec3 world_position;
+} IN;
+
+layout(location = 0) out vec4 albedo;
+layout(location = 1) out vec4 normal;
+layout(location = 2) out vec4 material;
+
+// specular strength, shininess, emission, unused
+uniform vec4 material_params;
+
+void main()
+{
+    albedo = IN.color;
+    normal = vec4(normalize(IN.normal), 0.0);
+    material = material_params;
+}
\ No newline at end of file
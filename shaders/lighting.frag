@@ -0,0 +1,199 @@
+#version 430 core
+
+// Lighting, shadows and fog shared by the forward shader (simple.frag) and the deferred lighting passes.
+// It is linked into their programs as a second fragment shader, so it has no main() of its own.
+
+// Has to match MAX_LIGHTS in light.rs
+#define MAX_LIGHTS 16
+
+#define DIRECTIONAL_LIGHT 0
+#define POINT_LIGHT 1
+#define SPOT_LIGHT 2
+
+struct Light {
+    int kind;
+    vec3 color;
+    vec3 position;
+    vec3 direction;
+    float range;
+    float cos_inner;
+    float cos_outer;
+    bool cast_shadows;
+};
+
+uniform Light lights[MAX_LIGHTS];
+uniform int light_count;
+
+// Has to match MAX_CASCADES in shadow.rs
+#define MAX_CASCADES 4
+
+uniform sampler2DArrayShadow shadow_map;
+uniform mat4 light_space_matrices[MAX_CASCADES];
+uniform float cascade_splits[MAX_CASCADES];
+uniform float cascade_bias[MAX_CASCADES];
+uniform int cascade_count;
+uniform float cascade_blend;
+uniform int shadow_pcf_radius;
+uniform bool shadows_enabled;
+
+uniform mat4 view_matrix;
+
+#define LINEAR_FOG 0
+#define EXPONENTIAL_FOG 1
+#define HEIGHT_FOG 2
+
+uniform bool fog_enabled;
+uniform int fog_mode;
+uniform vec3 fog_color;
+uniform float fog_density;
+uniform float fog_start;
+uniform float fog_end;
+uniform float fog_base_height;
+uniform float fog_height_falloff;
+uniform bool fog_use_environment;
+uniform samplerCube environment_map;
+
+uniform vec3 camera_position;
+
+// light reaching everything from the sky, darkened in creases by the screen-space ambient occlusion
+uniform vec3 ambient_color;
+uniform bool ssao_enabled;
+uniform sampler2D ssao_map;
+
+
+// 1.0 when fully lit, 0.0 when fully in shadow, looked up in a single cascade
+float cascade_shadow(int cascade, vec3 world_position, vec3 normal, vec3 light_direction)
+{
+    vec4 light_space = light_space_matrices[cascade] * vec4(world_position, 1.0);
+    vec3 coords = light_space.xyz / light_space.w * 0.5 + 0.5;
+
+    // outside of the far plane of the light, nothing can cast a shadow here
+    if (coords.z > 1.0) {
+        return 1.0;
+    }
+
+    // surfaces at a grazing angle to the light need more bias
+    float bias = max(cascade_bias[cascade] * (1.0 - dot(normal, -light_direction)), cascade_bias[cascade] * 0.1);
+
+    // percentage closer filtering, every sample is already a bilinear comparison
+    vec2 texel_size = 1.0 / vec2(textureSize(shadow_map, 0).xy);
+    float lit = 0.0;
+    for (int x = -shadow_pcf_radius; x <= shadow_pcf_radius; x++) {
+        for (int y = -shadow_pcf_radius; y <= shadow_pcf_radius; y++) {
+            lit += texture(shadow_map, vec4(coords.xy + vec2(x, y) * texel_size, float(cascade), coords.z - bias));
+        }
+    }
+    float samples = float((2 * shadow_pcf_radius + 1) * (2 * shadow_pcf_radius + 1));
+    return lit / samples;
+}
+
+// picks the cascade from the view depth, and fades into the next cascade at the end of each one
+float shadow_factor(vec3 world_position, vec3 normal, vec3 light_direction)
+{
+    float depth = -(view_matrix * vec4(world_position, 1.0)).z;
+
+    int cascade = 0;
+    while (cascade < cascade_count && depth > cascade_splits[cascade]) {
+        cascade++;
+    }
+    // past the last cascade there are no shadows
+    if (cascade == cascade_count) {
+        return 1.0;
+    }
+
+    float shadow = cascade_shadow(cascade, world_position, normal, light_direction);
+
+    float split_near = cascade == 0 ? 0.0 : cascade_splits[cascade - 1];
+    float blend_start = cascade_splits[cascade] - (cascade_splits[cascade] - split_near) * cascade_blend;
+    if (cascade_blend > 0.0 && cascade + 1 < cascade_count && depth > blend_start) {
+        float t = (depth - blend_start) / (cascade_splits[cascade] - blend_start);
+        shadow = mix(shadow, cascade_shadow(cascade + 1, world_position, normal, light_direction), t);
+    }
+    return shadow;
+}
+
+
+// the light reaching a surface with the given material, diffuse plus a Blinn-Phong highlight
+vec3 light_contribution(Light light, vec3 world_position, vec3 normal, vec4 material)
+{
+    vec3 light_direction;
+    float attenuation = 1.0;
+
+    if (light.kind == DIRECTIONAL_LIGHT) {
+        light_direction = -light.direction;
+        attenuation = (shadows_enabled && light.cast_shadows) ? shadow_factor(world_position, normal, light.direction) : 1.0;
+    } else {
+        vec3 to_light = light.position - world_position;
+        float distance = length(to_light);
+        light_direction = to_light / distance;
+
+        // smooth falloff which reaches zero at the range of the light
+        float falloff = clamp(1.0 - distance / light.range, 0.0, 1.0);
+        attenuation = falloff * falloff;
+
+        if (light.kind == SPOT_LIGHT) {
+            float cos_angle = dot(-light_direction, light.direction);
+            attenuation *= smoothstep(light.cos_outer, light.cos_inner, cos_angle);
+        }
+    }
+
+    float diffuse = max(dot(normal, light_direction), 0.0);
+
+    vec3 to_camera = normalize(camera_position - world_position);
+    vec3 halfway = normalize(light_direction + to_camera);
+    float shininess = max(material.y * 128.0, 1.0);
+    float specular = diffuse > 0.0 ? material.x * pow(max(dot(normal, halfway), 0.0), shininess) : 0.0;
+
+    return light.color * attenuation * (diffuse + specular);
+}
+
+// one light by its index in lights[], for the shaders linking this file that don't declare Light themselves
+vec3 indexed_light_contribution(int index, vec3 world_position, vec3 normal, vec4 material)
+{
+    return light_contribution(lights[index], world_position, normal, material);
+}
+
+// all the lights in the scene, or only the directional ones when the others are drawn as light volumes
+vec3 shade(vec3 albedo, vec3 world_position, vec3 normal, vec4 material, bool directional_only)
+{
+    vec3 lighting = vec3(0.0);
+    for (int i = 0; i < light_count; i++) {
+        if (directional_only && lights[i].kind != DIRECTIONAL_LIGHT) {
+            continue;
+        }
+        lighting += light_contribution(lights[i], world_position, normal, material);
+    }
+    float occlusion = ssao_enabled ? texture(ssao_map, gl_FragCoord.xy / vec2(textureSize(ssao_map, 0))).r : 1.0;
+    // material.z makes the surface glow on its own
+    return albedo * (ambient_color * occlusion + lighting + material.z);
+}
+
+// how much of the fragment is hidden by fog, from 0.0 (clear) to 1.0 (only fog)
+float fog_amount(vec3 to_fragment)
+{
+    float distance = length(to_fragment);
+
+    if (fog_mode == LINEAR_FOG) {
+        return clamp((distance - fog_start) / (fog_end - fog_start), 0.0, 1.0);
+    }
+    if (fog_mode == EXPONENTIAL_FOG) {
+        return 1.0 - exp(-fog_density * distance);
+    }
+
+    // height fog, the density along the view ray is integrated analytically
+    float camera_density = fog_density * exp(-fog_height_falloff * (camera_position.y - fog_base_height));
+    float height_change = fog_height_falloff * to_fragment.y;
+    float integral = abs(height_change) > 0.0001 ? (1.0 - exp(-height_change)) / height_change : 1.0;
+    return clamp(1.0 - exp(-camera_density * distance * integral), 0.0, 1.0);
+}
+
+vec3 apply_fog(vec3 shaded, vec3 world_position)
+{
+    if (!fog_enabled) {
+        return shaded;
+    }
+    vec3 to_fragment = world_position - camera_position;
+    // fading into the sky behind the fragment hides the far plane completely
+    vec3 fog = fog_use_environment ? texture(environment_map, to_fragment).rgb : fog_color;
+    return mix(shaded, fog, fog_amount(to_fragment));
+}
\ No newline at end of file
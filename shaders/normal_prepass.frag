@@ -0,0 +1,15 @@
+#version 430 core
+
+// the depth and normal prepass of the forward renderer, uses simple.vert
+in VS_OUTPUT {
+    vec4 color;
+    vec3 normal;
+    vec3 world_position;
+} IN;
+
+out vec4 normal;
+
+void main()
+{
+    normal = vec4(normalize(IN.normal), 0.0);
+}
\ No newline at end of file
@@ -0,0 +1,29 @@
+#version 430 core
+
+in VS_OUTPUT {
+    vec4 color;
+    vec3 normal;
+    vec3 world_position;
+} IN;
+
+// summed with additive blending, and the product of (1 - alpha) of every layer
+layout(location = 0) out vec4 accumulation;
+layout(location = 1) out vec4 revealage;
+
+uniform vec4 material_params;
+
+// from lighting.frag
+vec3 shade(vec3 albedo, vec3 world_position, vec3 normal, vec4 material, bool directional_only);
+vec3 apply_fog(vec3 shaded, vec3 world_position);
+
+// weighted blended order-independent transparency, lit like simple.frag
+void main()
+{
+    vec3 shaded = apply_fog(shade(vec3(IN.color), IN.world_position, normalize(IN.normal), material_params, false), IN.world_position);
+    float alpha = IN.color.a;
+
+    // closer and more opaque layers count for more, which stands in for the missing sorting
+    float weight = clamp(pow(min(1.0, alpha * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - gl_FragCoord.z * 0.9, 3.0), 1e-2, 3e3);
+    accumulation = vec4(shaded * alpha, alpha) * weight;
+    revealage = vec4(alpha);
+}
\ No newline at end of file
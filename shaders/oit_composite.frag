@@ -0,0 +1,21 @@
+#version 430 core
+
+in vec2 uv;
+
+out vec4 color;
+
+uniform sampler2D accumulation;
+uniform sampler2D revealage;
+
+// blends the weighted average of the transparent layers over the opaque scene
+void main()
+{
+    float revealed = texture(revealage, uv).r;
+    // no transparent surface here
+    if (revealed == 1.0) {
+        discard;
+    }
+
+    vec4 sum = texture(accumulation, uv);
+    color = vec4(sum.rgb / max(sum.a, 1e-5), 1.0 - revealed);
+}
\ No newline at end of file
@@ -0,0 +1,11 @@
+#version 430 core
+
+// 0 is left for the background, so the ids start at 1
+uniform uint object_id;
+
+layout(location = 0) out uint id;
+
+void main()
+{
+    id = object_id;
+}
\ No newline at end of file
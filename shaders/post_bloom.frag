@@ -0,0 +1,31 @@
+#version 430 core
+
+in vec2 uv;
+
+out vec4 color;
+
+// mipmapped by the chain, the lower levels act as a cheap wide blur
+uniform sampler2D image;
+uniform float threshold;
+uniform float intensity;
+
+// keeps only the part of the color brighter than the threshold
+vec3 bright(vec3 c)
+{
+    float brightness = max(c.r, max(c.g, c.b));
+    return c * max(brightness - threshold, 0.0) / max(brightness, 0.0001);
+}
+
+void main()
+{
+    vec3 bloom = vec3(0.0);
+    float weight = 1.0;
+    float total_weight = 0.0;
+    for (int lod = 1; lod <= 6; lod++) {
+        bloom += bright(textureLod(image, uv, float(lod)).rgb) * weight;
+        total_weight += weight;
+        weight *= 0.8;
+    }
+
+    color = vec4(texture(image, uv).rgb + intensity * bloom / total_weight, 1.0);
+}
\ No newline at end of file
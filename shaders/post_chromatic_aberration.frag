@@ -0,0 +1,18 @@
+#version 430 core
+
+in vec2 uv;
+
+out vec4 color;
+
+uniform sampler2D image;
+uniform float strength;
+
+// pulls the red and blue channels apart toward the edges of the screen, like a cheap lens
+void main()
+{
+    vec2 offset = (uv - 0.5) * strength;
+    float r = texture(image, uv + offset).r;
+    float g = texture(image, uv).g;
+    float b = texture(image, uv - offset).b;
+    color = vec4(r, g, b, 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,19 @@
+#version 430 core
+
+in vec2 uv;
+
+out vec4 color;
+
+uniform sampler2D image;
+uniform sampler3D lut;
+uniform float intensity;
+
+// looks the color up in a 3D table, the table is sampled between the texel centers so the edges aren't clamped
+void main()
+{
+    vec3 original = clamp(texture(image, uv).rgb, 0.0, 1.0);
+    float size = float(textureSize(lut, 0).x);
+    vec3 coords = original * ((size - 1.0) / size) + 0.5 / size;
+    vec3 graded = texture(lut, coords).rgb;
+    color = vec4(mix(original, graded, intensity), 1.0);
+}
\ No newline at end of file
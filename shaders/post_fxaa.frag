@@ -0,0 +1,51 @@
+#version 430 core
+
+in vec2 uv;
+
+out vec4 color;
+
+uniform sampler2D image;
+uniform vec2 texel_size;
+uniform float span_max;
+
+#define REDUCE_MUL (1.0 / 8.0)
+#define REDUCE_MIN (1.0 / 128.0)
+
+// fast approximate anti-aliasing, blurs along the edges found from the luma of the neighbours
+void main()
+{
+    vec3 luma = vec3(0.299, 0.587, 0.114);
+    vec3 rgb_nw = texture(image, uv + vec2(-1.0, -1.0) * texel_size).rgb;
+    vec3 rgb_ne = texture(image, uv + vec2(1.0, -1.0) * texel_size).rgb;
+    vec3 rgb_sw = texture(image, uv + vec2(-1.0, 1.0) * texel_size).rgb;
+    vec3 rgb_se = texture(image, uv + vec2(1.0, 1.0) * texel_size).rgb;
+    vec3 rgb_m = texture(image, uv).rgb;
+
+    float luma_nw = dot(rgb_nw, luma);
+    float luma_ne = dot(rgb_ne, luma);
+    float luma_sw = dot(rgb_sw, luma);
+    float luma_se = dot(rgb_se, luma);
+    float luma_m = dot(rgb_m, luma);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // the direction of the edge, across the steepest change in luma
+    vec2 direction = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+    float direction_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * (0.25 * REDUCE_MUL), REDUCE_MIN);
+    float inverse_direction_min = 1.0 / (min(abs(direction.x), abs(direction.y)) + direction_reduce);
+    direction = clamp(direction * inverse_direction_min, vec2(-span_max), vec2(span_max)) * texel_size;
+
+    vec3 rgb_a = 0.5 * (
+        texture(image, uv + direction * (1.0 / 3.0 - 0.5)).rgb +
+        texture(image, uv + direction * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+        texture(image, uv + direction * -0.5).rgb +
+        texture(image, uv + direction * 0.5).rgb);
+
+    // the wider blur went past the edge, fall back to the narrow one
+    float luma_b = dot(rgb_b, luma);
+    color = vec4((luma_b < luma_min || luma_b > luma_max) ? rgb_a : rgb_b, 1.0);
+}
\ No newline at end of file
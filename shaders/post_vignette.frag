@@ -0,0 +1,18 @@
+#version 430 core
+
+in vec2 uv;
+
+out vec4 color;
+
+uniform sampler2D image;
+uniform float intensity;
+uniform float radius;
+uniform float softness;
+
+// darkens the corners of the screen
+void main()
+{
+    float distance = length(uv - 0.5);
+    float vignette = smoothstep(radius, radius - softness, distance);
+    color = vec4(texture(image, uv).rgb * mix(1.0, vignette, intensity), 1.0);
+}
\ No newline at end of file
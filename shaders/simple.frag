@@ -6,12 +6,20 @@ out vec4 color;
 in VS_OUTPUT {
     vec4 color;
     vec3 normal;
+    vec3 world_position;
 } IN;
 
+// specular strength, shininess, emission, unused
+uniform vec4 material_params;
 
-vec3 lightDirection = normalize(vec3(0.8, -0.5, 0.6));
+// from lighting.frag
+vec3 shade(vec3 albedo, vec3 world_position, vec3 normal, vec4 material, bool directional_only);
+vec3 apply_fog(vec3 shaded, vec3 world_position);
 
 void main()
 {   
-    color =  vec4(vec3(IN.color) *  max(dot(IN.normal, -lightDirection), 0.0), IN.color[3]);
+    vec3 normal = normalize(IN.normal);
+    vec3 shaded = shade(vec3(IN.color), IN.world_position, normal, material_params, false);
+
+    color =  vec4(apply_fog(shaded, IN.world_position), IN.color[3]);
 }
\ No newline at end of file
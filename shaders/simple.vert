@@ -13,13 +13,22 @@ layout(location = 4) uniform mat4 model_matrix;
 out VS_OUTPUT{
     vec4 color;
     vec3 normal;
+    vec3 world_position;
 } OUT;
 
-
+// the exact sRGB transfer function, like texture::srgb_to_linear and the inverse of linear_to_srgb in tonemap.frag
+vec3 srgb_to_linear(vec3 srgb)
+{
+    vec3 low = srgb / 12.92;
+    vec3 high = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, vec3(lessThanEqual(srgb, vec3(0.04045))));
+}
 
 void main()
 {
     gl_Position = transformation_matrix * vec4(position, 1.0f);
-    OUT.color = color;
+    // vertex colors are picked in sRGB, but lighting has to happen in linear space
+    OUT.color = vec4(srgb_to_linear(color.rgb), color.a);
     OUT.normal = normalize(mat3(model_matrix) * normal);
+    OUT.world_position = vec3(model_matrix * vec4(position, 1.0f));
 }
\ No newline at end of file
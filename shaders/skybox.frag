@@ -0,0 +1,12 @@
+#version 430 core
+
+in vec3 direction;
+
+out vec4 color;
+
+uniform samplerCube skybox;
+
+void main()
+{
+    color = texture(skybox, direction);
+}
\ No newline at end of file
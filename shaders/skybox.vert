@@ -0,0 +1,15 @@
+#version 430 core
+
+layout(location = 0) in vec3 position;
+
+// perspective times the rotation of the camera, no translation
+layout(location = 3) uniform mat4 sky_matrix;
+
+out vec3 direction;
+
+void main()
+{
+    direction = position;
+    // z = w puts the sky exactly on the far plane
+    gl_Position = (sky_matrix * vec4(position, 1.0f)).xyww;
+}
\ No newline at end of file
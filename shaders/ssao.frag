@@ -0,0 +1,63 @@
+#version 430 core
+
+in vec2 uv;
+
+out vec4 occlusion;
+
+// Has to match MAX_KERNEL_SIZE in ssao.rs
+#define MAX_KERNEL_SIZE 64
+
+uniform sampler2D depth_texture;
+uniform sampler2D normal_texture; // world space normals
+uniform sampler2D noise_texture;  // small tiled texture of random rotations around the normal
+
+uniform vec3 kernel[MAX_KERNEL_SIZE];
+uniform int kernel_size;
+uniform float radius;
+uniform float bias;
+uniform vec2 noise_scale;
+
+uniform mat4 projection;
+uniform mat4 inverse_projection;
+uniform mat4 view_matrix;
+
+vec3 view_position(vec2 coords)
+{
+    float depth = texture(depth_texture, coords).r;
+    vec4 position = inverse_projection * vec4(vec3(coords, depth) * 2.0 - 1.0, 1.0);
+    return position.xyz / position.w;
+}
+
+// how much of the hemisphere above each pixel is blocked by nearby geometry, 1.0 when nothing is
+void main()
+{
+    // the sky is never occluded
+    if (texture(depth_texture, uv).r == 1.0) {
+        occlusion = vec4(1.0);
+        return;
+    }
+
+    vec3 position = view_position(uv);
+    vec3 normal = normalize(mat3(view_matrix) * texture(normal_texture, uv).xyz);
+
+    // turns the kernel randomly around the normal, the blur afterwards hides the pattern this leaves
+    vec3 random = vec3(texture(noise_texture, uv * noise_scale).xy, 0.0);
+    vec3 tangent = normalize(random - normal * dot(random, normal));
+    vec3 bitangent = cross(normal, tangent);
+    mat3 tbn = mat3(tangent, bitangent, normal);
+
+    float occluded = 0.0;
+    for (int i = 0; i < kernel_size; i++) {
+        vec3 sample_position = position + tbn * kernel[i] * radius;
+
+        vec4 offset = projection * vec4(sample_position, 1.0);
+        vec2 sample_uv = offset.xy / offset.w * 0.5 + 0.5;
+        float scene_depth = view_position(sample_uv).z;
+
+        // geometry far in front of the sample is something else entirely, like a helicopter above the ground
+        float in_range = smoothstep(0.0, 1.0, radius / abs(position.z - scene_depth));
+        occluded += (scene_depth >= sample_position.z + bias ? 1.0 : 0.0) * in_range;
+    }
+
+    occlusion = vec4(1.0 - occluded / float(kernel_size));
+}
\ No newline at end of file
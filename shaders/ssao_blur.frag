@@ -0,0 +1,21 @@
+#version 430 core
+
+in vec2 uv;
+
+out vec4 occlusion;
+
+uniform sampler2D image;
+uniform vec2 texel_size;
+uniform float intensity;
+
+// averages over the size of the noise texture, which removes the noise pattern again
+void main()
+{
+    float sum = 0.0;
+    for (int x = -2; x < 2; x++) {
+        for (int y = -2; y < 2; y++) {
+            sum += texture(image, uv + vec2(x, y) * texel_size).r;
+        }
+    }
+    occlusion = vec4(mix(1.0, sum / 16.0, intensity));
+}
\ No newline at end of file
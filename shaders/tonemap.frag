@@ -0,0 +1,50 @@
+#version 430 core
+
+in vec2 uv;
+
+out vec4 color;
+
+#define REINHARD 0
+#define ACES 1
+#define EXPOSURE 2
+
+uniform sampler2D image;
+uniform int operator;
+uniform float exposure;
+
+
+// Narkowicz' fit of the ACES filmic curve
+vec3 aces(vec3 x)
+{
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+// the exact sRGB transfer function, not just a 2.2 gamma
+vec3 linear_to_srgb(vec3 linear)
+{
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, vec3(lessThanEqual(linear, vec3(0.0031308))));
+}
+
+void main()
+{
+    vec4 hdr = texture(image, uv);
+    vec3 exposed = hdr.rgb * exposure;
+
+    vec3 mapped;
+    if (operator == REINHARD) {
+        mapped = exposed / (1.0 + exposed);
+    } else if (operator == ACES) {
+        mapped = aces(exposed);
+    } else {
+        mapped = 1.0 - exp(-exposed);
+    }
+
+    color = vec4(linear_to_srgb(clamp(mapped, 0.0, 1.0)), 1.0);
+}
\ No newline at end of file
@@ -1,46 +1,33 @@
+use crate::animation;
+use crate::game_loop;
 use crate::toolbox;
-use crate::scene_graph;
 
-unsafe fn tail(tail_rotor : &mut scene_graph::SceneNode, delta_time : f32)
-{
-    tail_rotor.rotation += delta_time * glm::vec3(-15.0, 0.0, 0.0);
-}
-
-unsafe fn main( main_rotor: &mut scene_graph::SceneNode, delta_time : f32)
-{
-    main_rotor.rotation += delta_time * glm::vec3(0.0, 10.0, 0.0);
-}
-
-unsafe fn zombie(body : &mut scene_graph::SceneNode, total_time: f32, offset: f32)
-{
-    let heading = toolbox::simple_heading_animation(total_time + offset);
-
-    body.position = glm::vec3(heading.x, body.position.y, heading.z);
-
-    body.rotation = glm::vec3(
-        heading.pitch,
-        heading.yaw,
-        heading.roll
-    );
-}
-
-pub unsafe fn move_body(body : &mut scene_graph::SceneNode, translation : glm::Vec3, delta_time : f32){
-    body.position += delta_time * translation
-}
-
-pub unsafe fn animate(root :  &mut scene_graph::SceneNode, total_time : f32, delta_time:f32, offset: f32)
-{
-    match root.name.as_str() {
-
-        "main" => main(root, delta_time),
-        "tail" => tail(root, delta_time),
-        "zombie" => zombie(root, total_time, offset),
-        _ => ()
+// simple_heading_animation goes around its path once in this many seconds
+const ZOMBIE_PATH_PERIOD: f32 = 2.0 * std::f32::consts::PI / 0.8;
+
+// The path of the zombie helicopters as a looping clip, sampled from simple_heading_animation at the given height.
+pub fn zombie_path(target: &str, height: f32, samples: usize) -> animation::AnimationClip {
+    let step = ZOMBIE_PATH_PERIOD / samples as f32;
+    let mut positions = Vec::with_capacity(samples + 1);
+    let mut rotations: Vec<animation::Keyframe> = Vec::with_capacity(samples + 1);
+    for i in 0..=samples {
+        let time = i as f32 * step;
+        let heading = toolbox::simple_heading_animation(time);
+        // the tangents from a little before and after, so the bezier curves join up smoothly
+        let before = toolbox::simple_heading_animation(time - 0.01);
+        let after = toolbox::simple_heading_animation(time + 0.01);
+        let tangent = glm::vec3(after.x - before.x, 0.0, after.z - before.z) / 0.02;
+        positions.push(animation::Keyframe::new(time, glm::vec3(heading.x, height, heading.z)).with_tangents(tangent, tangent));
+
+        // the yaw jumps from pi to -pi, turned into whole circles so the blending never spins it back
+        let mut rotation = glm::vec3(heading.pitch, heading.yaw, heading.roll);
+        if let Some(previous) = rotations.last() {
+            rotation = previous.value + game_loop::wrap_angles(&(rotation - previous.value));
+        }
+        rotations.push(animation::Keyframe::new(time, rotation));
     }
-    
 
-    // Recurse
-    for &child in &root.children {
-        animate(&mut *child, total_time, delta_time, offset);
-    }
+    animation::AnimationClip::new("zombie_path")
+        .with_track(animation::Track::new(target, animation::Property::Position, animation::Interpolation::CubicBezier, positions))
+        .with_track(animation::Track::new(target, animation::Property::Rotation, animation::Interpolation::Linear, rotations))
 }
\ No newline at end of file
@@ -0,0 +1,457 @@
+extern crate nalgebra_glm as glm;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game_loop;
+use crate::scene_graph;
+
+// Which transform of the node a track moves.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Property {
+    Position,
+    Rotation, // the angles around x, y and z, like SceneNode::rotation
+    Scale,
+}
+
+// How the values between two keyframes are worked out.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Interpolation {
+    Step,        // holds the value of the keyframe before until the next one
+    Linear,
+    Slerp,       // turns the shortest way between two rotations, anything else is blended linearly
+    CubicBezier, // eases along the tangents of the keyframes
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f32,
+    pub value: glm::Vec3,
+    // how fast the value changes per second coming into and going out of the keyframe, only used by CubicBezier
+    pub in_tangent: glm::Vec3,
+    pub out_tangent: glm::Vec3,
+}
+
+impl Keyframe {
+    // With flat tangents, so a bezier curve eases in and out of it.
+    pub fn new(time: f32, value: glm::Vec3) -> Keyframe {
+        Keyframe { time, value, in_tangent: glm::zero(), out_tangent: glm::zero() }
+    }
+
+    pub fn with_tangents(mut self, in_tangent: glm::Vec3, out_tangent: glm::Vec3) -> Keyframe {
+        self.in_tangent = in_tangent;
+        self.out_tangent = out_tangent;
+        self
+    }
+}
+
+// One property of one node over time. The target is the name of a node, or a path of names separated by slashes
+// starting at the node the clip is played on, like "body/door".
+pub struct Track {
+    pub target: String,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    pub keyframes: Vec<Keyframe>, // sorted by time
+}
+
+impl Track {
+    pub fn new(target: &str, property: Property, interpolation: Interpolation, mut keyframes: Vec<Keyframe>) -> Track {
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Track { target: String::from(target), property, interpolation, keyframes }
+    }
+
+    // The value at the given time, held at the first and last keyframes outside of them.
+    pub fn sample(&self, time: f32) -> Option<glm::Vec3> {
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        let next = self.keyframes.partition_point(|keyframe| keyframe.time <= time);
+        let (before, after) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let span = after.time - before.time;
+        let t = (time - before.time) / span;
+        Some(match self.interpolation {
+            Interpolation::Step => before.value,
+            Interpolation::Linear => glm::lerp(&before.value, &after.value, t),
+            Interpolation::Slerp if self.property == Property::Rotation => {
+                let from = euler_to_quat(&before.value);
+                let mut to = euler_to_quat(&after.value);
+                // q and -q are the same rotation, the one closer to from is the short way round
+                if glm::quat_dot(&from, &to) < 0.0 {
+                    to = -to;
+                }
+                quat_to_euler(&glm::quat_normalize(&glm::quat_slerp(&from, &to, t)))
+            }
+            Interpolation::Slerp => glm::lerp(&before.value, &after.value, t),
+            Interpolation::CubicBezier => {
+                // the tangents are per second, a third of the span of them gives the inner control points
+                let p0 = before.value;
+                let p1 = before.value + before.out_tangent * span / 3.0;
+                let p2 = after.value - after.in_tangent * span / 3.0;
+                let p3 = after.value;
+                let u = 1.0 - t;
+                p0 * (u * u * u) + p1 * (3.0 * u * u * t) + p2 * (3.0 * u * t * t) + p3 * (t * t * t)
+            }
+        })
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+}
+
+pub struct AnimationClip {
+    pub name: String,
+    pub tracks: Vec<Track>,
+}
+
+impl AnimationClip {
+    pub fn new(name: &str) -> AnimationClip {
+        AnimationClip { name: String::from(name), tracks: vec![] }
+    }
+
+    pub fn with_track(mut self, track: Track) -> AnimationClip {
+        self.tracks.push(track);
+        self
+    }
+
+    // Until the last keyframe of any track.
+    pub fn duration(&self) -> f32 {
+        self.tracks.iter().map(|track| track.duration()).fold(0.0, f32::max)
+    }
+}
+
+// What happens when the player reaches the end of the clip.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PlayMode {
+    Once,     // stops at the end, or at the start when playing backwards
+    Loop,     // jumps back to the start
+    PingPong, // turns around and plays backwards to the start, then forwards again
+}
+
+impl PlayMode {
+    pub fn next(self) -> PlayMode {
+        match self {
+            PlayMode::Once => PlayMode::Loop,
+            PlayMode::Loop => PlayMode::PingPong,
+            PlayMode::PingPong => PlayMode::Once,
+        }
+    }
+}
+
+// Plays a clip on the nodes under a root. The tracks are bound to their nodes once, when the player is made.
+pub struct AnimationPlayer {
+    pub clip: Rc<AnimationClip>,
+    pub mode: PlayMode,
+    pub speed: f32, // negative plays backwards
+    pub playing: bool,
+    pub time: f32,
+    targets: Vec<Option<*mut scene_graph::SceneNode>>, // the node of every track, none if it wasn't found
+}
+
+impl AnimationPlayer {
+    pub unsafe fn new(clip: Rc<AnimationClip>, root: &mut scene_graph::SceneNode, mode: PlayMode) -> AnimationPlayer {
+        let targets = clip.tracks.iter().map(|track| find_node(&mut *root, &track.target)).collect();
+        AnimationPlayer { clip, mode, speed: 1.0, playing: true, time: 0.0, targets }
+    }
+
+    // Like new(), but fails naming every target that isn't under the root.
+    pub unsafe fn bind(clip: Rc<AnimationClip>, root: &mut scene_graph::SceneNode, mode: PlayMode) -> Result<AnimationPlayer, String> {
+        let player = AnimationPlayer::new(clip, root, mode);
+        let missing = player.missing();
+        if missing.is_empty() {
+            return Ok(player);
+        }
+        Err(format!("Clip {} has no node {} under {}", player.clip.name, missing.join(", "), root.name))
+    }
+
+    // The targets of the tracks that didn't match any node, those tracks do nothing.
+    pub fn missing(&self) -> Vec<&str> {
+        self.clip.tracks.iter().zip(self.targets.iter())
+            .filter(|(_, target)| target.is_none())
+            .map(|(track, _)| track.target.as_str())
+            .collect()
+    }
+
+    pub fn advance(&mut self, delta_time: f32) {
+        if !self.playing {
+            return;
+        }
+        let duration = self.clip.duration();
+        self.time += delta_time * self.speed;
+        match self.mode {
+            PlayMode::Once => {
+                if self.time <= 0.0 || self.time >= duration {
+                    self.time = self.time.clamp(0.0, duration);
+                    self.playing = false;
+                }
+            }
+            PlayMode::Loop if duration > 0.0 => self.time = self.time.rem_euclid(duration),
+            // one round is there and back again
+            PlayMode::PingPong if duration > 0.0 => self.time = self.time.rem_euclid(2.0 * duration),
+            _ => self.time = 0.0,
+        }
+    }
+
+    // Where in the clip the player is, the way back of a ping-pong counted backwards.
+    pub fn clip_time(&self) -> f32 {
+        let duration = self.clip.duration();
+        if self.mode == PlayMode::PingPong && self.time > duration {
+            2.0 * duration - self.time
+        } else {
+            self.time
+        }
+    }
+
+    // Adds the clip at the current time to a pose, to be blended with other clips.
+    pub fn sample_into(&self, weight: f32, pose: &mut Pose) {
+        let time = self.clip_time();
+        for (track, target) in self.clip.tracks.iter().zip(self.targets.iter()) {
+            if let (Some(node), Some(value)) = (target, track.sample(time)) {
+                pose.add(*node, track.property, value, weight);
+            }
+        }
+    }
+
+    // Sets the nodes to the clip at the current time.
+    pub unsafe fn apply(&self) {
+        let mut pose = Pose::default();
+        self.sample_into(1.0, &mut pose);
+        pose.apply();
+    }
+}
+
+// The properties of nodes sampled from any number of clips, each weighed by how much of it should show.
+// Properties no clip has a track for are left alone.
+#[derive(Default)]
+pub struct Pose {
+    values: Vec<PoseValue>,
+}
+
+struct PoseValue {
+    node: *mut scene_graph::SceneNode,
+    property: Property,
+    first: glm::Vec3, // the rotations are turned to the nearest angles to the first one before they are blended
+    sum: glm::Vec3,   // weighted
+    weight: f32,
+}
+
+impl Pose {
+    pub fn add(&mut self, node: *mut scene_graph::SceneNode, property: Property, value: glm::Vec3, weight: f32) {
+        if weight <= 0.0 {
+            return;
+        }
+        match self.values.iter_mut().find(|pose_value| pose_value.node == node && pose_value.property == property) {
+            Some(pose_value) => {
+                let value = match property {
+                    Property::Rotation => pose_value.first + game_loop::wrap_angles(&(value - pose_value.first)),
+                    _ => value,
+                };
+                pose_value.sum += value * weight;
+                pose_value.weight += weight;
+            }
+            None => self.values.push(PoseValue { node, property, first: value, sum: value * weight, weight }),
+        }
+    }
+
+    // Sets every property to the weighted average of what the clips gave it.
+    pub unsafe fn apply(&self) {
+        for pose_value in &self.values {
+            let node = &mut *pose_value.node;
+            let value = pose_value.sum / pose_value.weight;
+            match pose_value.property {
+                Property::Position => node.position = value,
+                Property::Rotation => node.rotation = value,
+                Property::Scale => node.scale = value,
+            }
+        }
+    }
+}
+
+// A node by path, "body/door" being the child named door of the root named body, or else the first node
+// with the name anywhere under the root. The root is borrowed mutably, the clips change the nodes through the pointer.
+pub unsafe fn find_node(root: &mut scene_graph::SceneNode, target: &str) -> Option<*mut scene_graph::SceneNode> {
+    let root = root as *mut scene_graph::SceneNode;
+    if target.contains('/') {
+        let mut names = target.split('/');
+        if names.next() != Some((*root).name.as_str()) {
+            return None;
+        }
+        let mut node = root;
+        for name in names {
+            node = *(*node).children.iter().find(|&&child| (*child).name == name)?;
+        }
+        return Some(node);
+    }
+
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        if (*node).name == target {
+            return Some(node);
+        }
+        // backwards, so the first child is searched first
+        stack.extend((*node).children.iter().rev().cloned());
+    }
+    None
+}
+
+// The rotation update_node_transformations makes of the angles, around x, then y, then z.
+pub fn euler_to_quat(angles: &glm::Vec3) -> glm::Quat {
+    glm::quat_angle_axis(angles.x, &glm::Vec3::x())
+        * glm::quat_angle_axis(angles.y, &glm::Vec3::y())
+        * glm::quat_angle_axis(angles.z, &glm::Vec3::z())
+}
+
+// Splits a rotation back into the angles around x, y and z.
+pub fn quat_to_euler(rotation: &glm::Quat) -> glm::Vec3 {
+    let m = glm::quat_to_mat3(rotation);
+    let y = m[(0, 2)].clamp(-1.0, 1.0).asin();
+    let x = (-m[(1, 2)]).atan2(m[(2, 2)]);
+    let z = (-m[(0, 1)]).atan2(m[(0, 0)]);
+    glm::vec3(x, y, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Random;
+
+    fn track(interpolation: Interpolation, property: Property, keyframes: Vec<Keyframe>) -> Track {
+        Track::new("node", property, interpolation, keyframes)
+    }
+
+    fn assert_close(a: &glm::Vec3, b: &glm::Vec3) {
+        assert!(glm::distance(a, b) < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    // the same rotation, q and -q both counting
+    fn assert_same_rotation(a: &glm::Vec3, b: &glm::Vec3) {
+        let dot = glm::quat_dot(&euler_to_quat(a), &euler_to_quat(b));
+        assert!(dot.abs() > 1.0 - 1e-5, "{:?} and {:?} turn differently", a, b);
+    }
+
+    #[test]
+    fn samples_hold_outside_the_keyframes() {
+        let keyframes = vec![Keyframe::new(1.0, glm::vec3(1.0, 2.0, 3.0)), Keyframe::new(2.0, glm::vec3(4.0, 5.0, 6.0))];
+        for interpolation in [Interpolation::Step, Interpolation::Linear, Interpolation::Slerp, Interpolation::CubicBezier] {
+            let track = track(interpolation, Property::Position, keyframes.clone());
+            assert_eq!(track.sample(0.0), Some(glm::vec3(1.0, 2.0, 3.0)));
+            assert_eq!(track.sample(1.0), Some(glm::vec3(1.0, 2.0, 3.0)));
+            assert_eq!(track.sample(2.0), Some(glm::vec3(4.0, 5.0, 6.0)));
+            assert_eq!(track.sample(9.0), Some(glm::vec3(4.0, 5.0, 6.0)));
+        }
+        assert_eq!(track(Interpolation::Linear, Property::Position, vec![]).sample(0.0), None);
+    }
+
+    #[test]
+    fn keyframes_are_sorted() {
+        let track = track(Interpolation::Linear, Property::Position, vec![
+            Keyframe::new(2.0, glm::vec3(2.0, 0.0, 0.0)),
+            Keyframe::new(0.0, glm::vec3(0.0, 0.0, 0.0)),
+        ]);
+        assert_eq!(track.duration(), 2.0);
+        assert_close(&track.sample(0.5).unwrap(), &glm::vec3(0.5, 0.0, 0.0));
+    }
+
+    #[test]
+    fn step_holds_the_keyframe_before() {
+        let track = track(Interpolation::Step, Property::Position, vec![
+            Keyframe::new(0.0, glm::vec3(0.0, 0.0, 0.0)),
+            Keyframe::new(1.0, glm::vec3(1.0, 0.0, 0.0)),
+            Keyframe::new(2.0, glm::vec3(5.0, 0.0, 0.0)),
+        ]);
+        assert_eq!(track.sample(0.99), Some(glm::vec3(0.0, 0.0, 0.0)));
+        assert_eq!(track.sample(1.5), Some(glm::vec3(1.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn linear_goes_straight_between_keyframes() {
+        let track = track(Interpolation::Linear, Property::Position, vec![
+            Keyframe::new(0.0, glm::vec3(0.0, 0.0, 0.0)),
+            Keyframe::new(1.0, glm::vec3(2.0, -4.0, 0.0)),
+            Keyframe::new(3.0, glm::vec3(0.0, 0.0, 0.0)),
+        ]);
+        assert_close(&track.sample(0.25).unwrap(), &glm::vec3(0.5, -1.0, 0.0));
+        assert_close(&track.sample(2.0).unwrap(), &glm::vec3(1.0, -2.0, 0.0));
+    }
+
+    #[test]
+    fn bezier_follows_the_tangents() {
+        let start = glm::vec3(0.0, 0.0, 0.0);
+        let end = glm::vec3(3.0, 0.0, 0.0);
+        // flat tangents ease in and out, halfway through in time is halfway in value
+        let flat = track(Interpolation::CubicBezier, Property::Position, vec![Keyframe::new(0.0, start), Keyframe::new(2.0, end)]);
+        assert_close(&flat.sample(1.0).unwrap(), &glm::vec3(1.5, 0.0, 0.0));
+        // and start slower than a straight line, which would be at 0.3
+        assert!(flat.sample(0.2).unwrap().x < 0.15);
+
+        // tangents the same as the slope make a straight line
+        let slope = (end - start) / 2.0;
+        let straight = track(Interpolation::CubicBezier, Property::Position, vec![
+            Keyframe::new(0.0, start).with_tangents(slope, slope),
+            Keyframe::new(2.0, end).with_tangents(slope, slope),
+        ]);
+        for time in [0.3, 1.0, 1.7] {
+            assert_close(&straight.sample(time).unwrap(), &(start + slope * time));
+        }
+
+        // the speed leaving the first keyframe is its out tangent
+        let tangent = glm::vec3(0.0, 5.0, -1.0);
+        let eased = track(Interpolation::CubicBezier, Property::Position, vec![
+            Keyframe::new(0.0, start).with_tangents(glm::zero(), tangent),
+            Keyframe::new(2.0, end),
+        ]);
+        let speed = (eased.sample(0.001).unwrap() - start) / 0.001;
+        assert!(glm::distance(&speed, &tangent) < 0.05, "{:?}", speed);
+    }
+
+    #[test]
+    fn slerp_turns_the_short_way() {
+        // 3 and -3 radians are a little over half a turn apart the long way, and a little under it the short way
+        let track = track(Interpolation::Slerp, Property::Rotation, vec![
+            Keyframe::new(0.0, glm::vec3(0.0, 3.0, 0.0)),
+            Keyframe::new(1.0, glm::vec3(0.0, -3.0, 0.0)),
+        ]);
+        assert_same_rotation(&track.sample(0.5).unwrap(), &glm::vec3(0.0, std::f32::consts::PI, 0.0));
+        assert_same_rotation(&track.sample(0.25).unwrap(), &glm::vec3(0.0, 3.0 + (std::f32::consts::PI - 3.0) / 2.0, 0.0));
+    }
+
+    #[test]
+    fn slerp_blends_other_properties_linearly() {
+        let track = track(Interpolation::Slerp, Property::Scale, vec![
+            Keyframe::new(0.0, glm::vec3(1.0, 3.0, 1.0)),
+            Keyframe::new(1.0, glm::vec3(1.0, -3.0, 1.0)),
+        ]);
+        assert_close(&track.sample(0.5).unwrap(), &glm::vec3(1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn euler_angles_round_trip() {
+        let mut random = Random::new(5);
+        for _ in 0..100 {
+            // y within a quarter turn either way, past that the same rotation has other angles
+            let angles = glm::vec3(
+                (random.float() * 2.0 - 1.0) * 3.1,
+                (random.float() * 2.0 - 1.0) * 1.5,
+                (random.float() * 2.0 - 1.0) * 3.1,
+            );
+            assert_close(&quat_to_euler(&euler_to_quat(&angles)), &angles);
+        }
+        // outside of that only the rotation is kept
+        let angles = glm::vec3(0.5, 2.5, -1.0);
+        assert_same_rotation(&quat_to_euler(&euler_to_quat(&angles)), &angles);
+    }
+
+    #[test]
+    fn euler_order_matches_the_scene_graph() {
+        let angles = glm::vec3(0.4, -1.1, 2.0);
+        let matrix = glm::rotation(angles.x, &glm::Vec3::x()) * glm::rotation(angles.y, &glm::Vec3::y()) * glm::rotation(angles.z, &glm::Vec3::z());
+        let from_quat = glm::quat_to_mat4(&euler_to_quat(&angles));
+        assert!((matrix - from_quat).abs().max() < 1e-5);
+    }
+}
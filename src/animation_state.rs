@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use crate::animation;
+
+// A clip playing in a mixer, and how much of it shows.
+pub struct Layer {
+    pub player: animation::AnimationPlayer,
+    pub weight: f32,
+    target_weight: f32,
+    fade_rate: f32, // weight per second
+    synced: bool,
+}
+
+// Blends any number of clips into one pose. The weights can be set by hand, or faded over time.
+// Synced layers play their loops in step: they are all at the same point of their clip, and get there at the
+// blended rate, so loops doing the same thing at different speeds blend without fighting over where they are.
+#[derive(Default)]
+pub struct AnimationMixer {
+    pub layers: Vec<Layer>,
+    pub phase: f32, // of the synced layers, 0 at the start of their clips and 1 at the end
+}
+
+impl AnimationMixer {
+    pub fn new() -> AnimationMixer {
+        AnimationMixer::default()
+    }
+
+    // Returns the index of the layer.
+    pub fn add(&mut self, player: animation::AnimationPlayer, weight: f32) -> usize {
+        self.layers.push(Layer { player, weight, target_weight: weight, fade_rate: 0.0, synced: false });
+        self.layers.len() - 1
+    }
+
+    // Like add(), but the layer plays at the phase of the other synced layers.
+    pub fn add_synced(&mut self, player: animation::AnimationPlayer, weight: f32) -> usize {
+        let layer = self.add(player, weight);
+        self.layers[layer].synced = true;
+        layer
+    }
+
+    pub fn set_weight(&mut self, layer: usize, weight: f32) {
+        let layer = &mut self.layers[layer];
+        layer.weight = weight;
+        layer.target_weight = weight;
+    }
+
+    // Fades the layer in and every other layer out over the duration.
+    pub fn fade_to(&mut self, layer: usize, duration: f32) {
+        for (i, other) in self.layers.iter_mut().enumerate() {
+            other.target_weight = if i == layer { 1.0 } else { 0.0 };
+            if duration > 0.0 {
+                other.fade_rate = 1.0 / duration;
+            } else {
+                other.weight = other.target_weight;
+            }
+        }
+        // a clip coming in from nothing starts from the beginning, unless it is synced to the others
+        let layer = &mut self.layers[layer];
+        if layer.weight <= 0.0 && !layer.synced {
+            layer.player.time = 0.0;
+            layer.player.playing = true;
+        }
+    }
+
+    pub fn advance(&mut self, delta_time: f32) {
+        // the synced layers go round as many times a second as the weighted average of theirs
+        let (mut rate, mut weight) = (0.0, 0.0);
+        for layer in self.layers.iter().filter(|layer| layer.synced && layer.weight > 0.0) {
+            let duration = layer.player.clip.duration();
+            if duration > 0.0 {
+                rate += layer.weight * layer.player.speed / duration;
+                weight += layer.weight;
+            }
+        }
+        if weight > 0.0 {
+            self.phase = (self.phase + rate / weight * delta_time).rem_euclid(1.0);
+        }
+
+        for layer in self.layers.iter_mut() {
+            let step = layer.fade_rate * delta_time;
+            layer.weight = if layer.weight < layer.target_weight {
+                (layer.weight + step).min(layer.target_weight)
+            } else {
+                (layer.weight - step).max(layer.target_weight)
+            };
+            if layer.synced {
+                layer.player.time = self.phase * layer.player.clip.duration();
+            } else if layer.weight > 0.0 {
+                // the ones that can't be seen hold still
+                layer.player.advance(delta_time);
+            }
+        }
+    }
+
+    pub unsafe fn apply(&self) {
+        let mut pose = animation::Pose::default();
+        for layer in self.layers.iter() {
+            layer.player.sample_into(layer.weight, &mut pose);
+        }
+        pose.apply();
+    }
+}
+
+// When a transition is taken. The parameters are set from outside, and are 0 until they are.
+#[derive(Clone, Debug)]
+pub enum Condition {
+    Above(String, f32),
+    Below(String, f32),
+    After(f32), // seconds in the state
+    Finished,   // the clip of the state has stopped playing
+    All(Vec<Condition>),
+}
+
+pub struct State {
+    pub name: String,
+    pub layer: usize, // in the mixer
+}
+
+pub struct Transition {
+    pub from: Option<usize>, // none is from any state
+    pub to: usize,
+    pub condition: Condition,
+    pub duration: f32, // of the cross-fade
+}
+
+// Picks the clip to play from a few parameters, and cross-fades to it when the state changes.
+// The first transition out of the current state whose condition holds is taken.
+pub struct StateMachine {
+    pub mixer: AnimationMixer,
+    pub states: Vec<State>,
+    pub transitions: Vec<Transition>,
+    pub parameters: HashMap<String, f32>,
+    pub current: usize,
+    pub time_in_state: f32,
+}
+
+impl StateMachine {
+    pub fn new(mixer: AnimationMixer) -> StateMachine {
+        StateMachine { mixer, states: vec![], transitions: vec![], parameters: HashMap::new(), current: 0, time_in_state: 0.0 }
+    }
+
+    // The first state added is the one the machine starts in.
+    pub fn add_state(&mut self, name: &str, layer: usize) -> usize {
+        self.states.push(State { name: String::from(name), layer });
+        if self.states.len() == 1 {
+            self.mixer.fade_to(layer, 0.0);
+        }
+        self.states.len() - 1
+    }
+
+    // From a state by name, or from any state with "*".
+    pub fn add_transition(&mut self, from: &str, to: &str, condition: Condition, duration: f32) {
+        let from = if from == "*" { None } else { Some(self.state_index(from)) };
+        let to = self.state_index(to);
+        self.transitions.push(Transition { from, to, condition, duration });
+    }
+
+    fn state_index(&self, name: &str) -> usize {
+        self.states.iter().position(|state| state.name == name).unwrap_or_else(|| panic!("No animation state named {}", name))
+    }
+
+    pub fn set(&mut self, parameter: &str, value: f32) {
+        self.parameters.insert(String::from(parameter), value);
+    }
+
+    pub fn state(&self) -> &str {
+        &self.states[self.current].name
+    }
+
+    fn holds(&self, condition: &Condition) -> bool {
+        let parameter = |name: &String| self.parameters.get(name).cloned().unwrap_or(0.0);
+        match condition {
+            Condition::Above(name, value) => parameter(name) > *value,
+            Condition::Below(name, value) => parameter(name) < *value,
+            Condition::After(seconds) => self.time_in_state >= *seconds,
+            Condition::Finished => !self.mixer.layers[self.states[self.current].layer].player.playing,
+            Condition::All(conditions) => conditions.iter().all(|condition| self.holds(condition)),
+        }
+    }
+
+    // Takes a transition if one is due, and moves the blend on. Returns the new state when it changed.
+    pub unsafe fn update(&mut self, delta_time: f32) -> Option<&str> {
+        let transition = self.transitions.iter()
+            .find(|transition| transition.from.is_none_or(|from| from == self.current) && transition.to != self.current && self.holds(&transition.condition))
+            .map(|transition| (transition.to, transition.duration));
+        if let Some((to, duration)) = transition {
+            self.current = to;
+            self.time_in_state = 0.0;
+            self.mixer.fade_to(self.states[to].layer, duration);
+        }
+
+        self.mixer.advance(delta_time);
+        self.mixer.apply();
+        self.time_in_state += delta_time;
+        match transition {
+            Some(_) => Some(self.state()),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_graph;
+    use std::f32::consts::TAU;
+    use std::rc::Rc;
+
+    // A rotor node, and a clip turning it once around in the given time.
+    fn player(root: &mut scene_graph::SceneNode, duration: f32, mode: animation::PlayMode) -> animation::AnimationPlayer {
+        let clip = animation::AnimationClip::new("turn").with_track(animation::Track::new("main", animation::Property::Rotation, animation::Interpolation::Linear, vec![
+            animation::Keyframe::new(0.0, glm::vec3(0.0, 0.0, 0.0)),
+            animation::Keyframe::new(duration, glm::vec3(0.0, TAU, 0.0)),
+        ]));
+        unsafe { animation::AnimationPlayer::bind(Rc::new(clip), root, mode).unwrap() }
+    }
+
+    fn rotor() -> scene_graph::Node {
+        let mut root = scene_graph::SceneNode::new();
+        let mut main = scene_graph::SceneNode::new();
+        main.name = String::from("main");
+        root.add_child(&main);
+        root
+    }
+
+    fn weights(mixer: &AnimationMixer) -> Vec<f32> {
+        mixer.layers.iter().map(|layer| (layer.weight * 1000.0).round() / 1000.0).collect()
+    }
+
+    #[test]
+    fn fades_cross_over_the_duration() {
+        let mut root = rotor();
+        let mut mixer = AnimationMixer::new();
+        let first = mixer.add(player(&mut root, 1.0, animation::PlayMode::Loop), 1.0);
+        let second = mixer.add(player(&mut root, 1.0, animation::PlayMode::Loop), 0.0);
+        mixer.layers[second].player.time = 0.5;
+
+        mixer.fade_to(second, 0.5);
+        // coming in from nothing, it starts from the beginning
+        assert_eq!(mixer.layers[second].player.time, 0.0);
+        mixer.advance(0.125);
+        assert_eq!(weights(&mixer), vec![0.75, 0.25]);
+        mixer.advance(1.0);
+        assert_eq!(weights(&mixer), vec![0.0, 1.0]);
+
+        // the one faded out holds still
+        let time = mixer.layers[first].player.time;
+        mixer.advance(0.1);
+        assert_eq!(mixer.layers[first].player.time, time);
+    }
+
+    #[test]
+    fn synced_layers_share_a_phase() {
+        let mut root = rotor();
+        let mut mixer = AnimationMixer::new();
+        let slow = mixer.add_synced(player(&mut root, 2.0, animation::PlayMode::Loop), 0.5);
+        let fast = mixer.add_synced(player(&mut root, 1.0, animation::PlayMode::Loop), 0.5);
+        let hidden = mixer.add_synced(player(&mut root, 4.0, animation::PlayMode::Loop), 0.0);
+
+        // half a loop a second and one loop a second, blended evenly
+        mixer.advance(0.2);
+        assert!((mixer.phase - 0.15).abs() < 1e-5);
+        for layer in [slow, fast, hidden] {
+            let player = &mixer.layers[layer].player;
+            assert!((player.time / player.clip.duration() - mixer.phase).abs() < 1e-5);
+        }
+
+        // at the same phase the clips agree on the angle, so the blend doesn't pull it anywhere
+        unsafe {
+            mixer.apply();
+            let main = &*root.children[0];
+            assert!((main.rotation.y - TAU * 0.15).abs() < 1e-4);
+        }
+
+        // fading in doesn't restart a synced layer
+        mixer.fade_to(hidden, 1.0);
+        mixer.advance(0.0);
+        assert!((mixer.layers[hidden].player.time - 0.15 * 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn transitions_follow_the_parameters() {
+        let mut root = rotor();
+        let mut mixer = AnimationMixer::new();
+        let layers = [
+            mixer.add(player(&mut root, 1.0, animation::PlayMode::Loop), 0.0),
+            mixer.add(player(&mut root, 1.0, animation::PlayMode::Loop), 0.0),
+            mixer.add(player(&mut root, 1.0, animation::PlayMode::Once), 0.0),
+        ];
+        let mut machine = StateMachine::new(mixer);
+        for (name, layer) in ["ground", "air", "landed"].iter().zip(layers) {
+            machine.add_state(name, layer);
+        }
+        machine.add_transition("ground", "air", Condition::Above(String::from("height"), 1.0), 0.5);
+        machine.add_transition("air", "landed", Condition::All(vec![Condition::Below(String::from("height"), 0.5), Condition::After(1.0)]), 0.0);
+        machine.add_transition("landed", "ground", Condition::Finished, 0.0);
+        machine.add_transition("*", "air", Condition::Above(String::from("bounce"), 0.0), 0.0);
+
+        unsafe {
+            // the first state is in full from the start
+            assert_eq!(machine.state(), "ground");
+            assert_eq!(weights(&machine.mixer), vec![1.0, 0.0, 0.0]);
+            assert_eq!(machine.update(0.1), None);
+
+            machine.set("height", 2.0);
+            assert_eq!(machine.update(0.125), Some("air"));
+            assert_eq!(weights(&machine.mixer), vec![0.75, 0.25, 0.0]);
+
+            // low enough, but not long enough in the air
+            machine.set("height", 0.0);
+            assert_eq!(machine.update(0.5), None);
+            assert_eq!(machine.update(0.5), None);
+            assert_eq!(weights(&machine.mixer), vec![0.0, 1.0, 0.0]);
+            assert_eq!(machine.update(0.1), Some("landed"));
+            assert_eq!(weights(&machine.mixer), vec![0.0, 0.0, 1.0]);
+
+            // the clip of landed plays once, then it goes back to the ground
+            assert_eq!(machine.update(0.5), None);
+            assert_eq!(machine.update(0.6), None);
+            assert_eq!(machine.update(0.1), Some("ground"));
+
+            // from any state, but not into itself again
+            machine.set("bounce", 1.0);
+            assert_eq!(machine.update(0.1), Some("air"));
+            assert_eq!(machine.update(0.1), None);
+            assert_eq!(machine.state(), "air");
+        }
+    }
+}
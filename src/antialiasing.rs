@@ -0,0 +1,47 @@
+// How the edges are smoothed, picked once at startup with --aa=<mode>.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AntiAliasing {
+    Off,
+    Msaa(u16), // multisampled window context and scene framebuffer, resolved before post-processing
+    Fxaa,      // cheaper full-screen pass after the tone mapping
+}
+
+impl AntiAliasing {
+    // Parses "off", "fxaa" or "msaa<samples>", like "msaa4".
+    pub fn parse(value: &str) -> Result<AntiAliasing, String> {
+        match value {
+            "off" => Ok(AntiAliasing::Off),
+            "fxaa" => Ok(AntiAliasing::Fxaa),
+            _ if value.starts_with("msaa") => {
+                let samples: u16 = value["msaa".len()..].parse()
+                    .map_err(|_| format!("Invalid sample count in {}", value))?;
+                if !samples.is_power_of_two() || !(2..=16).contains(&samples) {
+                    return Err(format!("MSAA needs 2, 4, 8 or 16 samples, got {}", samples));
+                }
+                Ok(AntiAliasing::Msaa(samples))
+            },
+            _ => Err(format!("Unknown anti-aliasing mode {}, try off, fxaa or msaa4", value)),
+        }
+    }
+
+    // Looks for --aa=<mode> among the command line arguments, defaulting to FXAA.
+    pub fn from_args() -> AntiAliasing {
+        for arg in std::env::args().skip(1) {
+            if let Some(value) = arg.strip_prefix("--aa=") {
+                match AntiAliasing::parse(value) {
+                    Ok(mode) => return mode,
+                    Err(e) => println!("{}, falling back to FXAA.", e),
+                }
+            }
+        }
+        AntiAliasing::Fxaa
+    }
+
+    // The number of samples for the multisampled framebuffers, 0 when they aren't used.
+    pub fn samples(self) -> u16 {
+        match self {
+            AntiAliasing::Msaa(samples) => samples,
+            _ => 0,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+extern crate nalgebra_glm as glm;
+
+// An axis-aligned bounding box.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+}
+
+impl Aabb {
+    // The smallest box around the points, given as x, y, z triples like Mesh::vertices.
+    pub fn from_points(points: &[f32]) -> Aabb {
+        // a mesh without vertices gets an empty box at the origin
+        if points.len() < 3 {
+            return Aabb { min: glm::zero(), max: glm::zero() };
+        }
+
+        let mut min = glm::vec3(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = glm::vec3(f32::MIN, f32::MIN, f32::MIN);
+        for point in points.chunks_exact(3) {
+            let point = glm::vec3(point[0], point[1], point[2]);
+            min = glm::min2(&min, &point);
+            max = glm::max2(&max, &point);
+        }
+        Aabb { min, max }
+    }
+
+    pub fn center(&self) -> glm::Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extents(&self) -> glm::Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: glm::min2(&self.min, &other.min),
+            max: glm::max2(&self.max, &other.max),
+        }
+    }
+
+    // The box around this box after it has been moved by the matrix, which may be a little loose when rotated.
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Aabb {
+        let center = matrix * glm::vec4(self.center().x, self.center().y, self.center().z, 1.0);
+        let center = glm::vec3(center.x, center.y, center.z);
+
+        // every axis of the new box is reached by the extents along the absolute rotated axes
+        let rotation = glm::mat4_to_mat3(matrix);
+        let abs_rotation = rotation.map(|value| value.abs());
+        let extents = abs_rotation * self.extents();
+
+        Aabb { min: center - extents, max: center + extents }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BoundingSphere {
+    pub center: glm::Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // A sphere around the center of the box which reaches the farthest of the points.
+    pub fn from_points(points: &[f32], aabb: &Aabb) -> BoundingSphere {
+        let center = aabb.center();
+        let radius = points.chunks_exact(3)
+            .map(|point| glm::distance(&glm::vec3(point[0], point[1], point[2]), &center))
+            .fold(0.0f32, f32::max);
+        BoundingSphere { center, radius }
+    }
+
+    // The sphere moved by the matrix, grown by the largest scale so it still covers everything.
+    pub fn transformed(&self, matrix: &glm::Mat4) -> BoundingSphere {
+        let center = matrix * glm::vec4(self.center.x, self.center.y, self.center.z, 1.0);
+        let rotation = glm::mat4_to_mat3(matrix);
+        let scale = (0..3).map(|i| glm::length(&rotation.column(i).into_owned())).fold(0.0f32, f32::max);
+        BoundingSphere {
+            center: glm::vec3(center.x, center.y, center.z),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+// The six planes of a view frustum, with the normals pointing inwards.
+// Each plane is (normal, distance), so a point p is inside when dot(normal, p) + distance >= 0.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [glm::Vec4; 6],
+}
+
+impl Frustum {
+    // Pulls the planes straight out of a view-projection matrix, like Camera::move_camera_matrix() (Gribb and Hartmann).
+    pub fn from_matrix(matrix: &glm::Mat4) -> Frustum {
+        let row = |i: usize| glm::vec4(matrix[(i, 0)], matrix[(i, 1)], matrix[(i, 2)], matrix[(i, 3)]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let mut planes = [w + x, w - x, w + y, w - y, w + z, w - z]; // left, right, bottom, top, near, far
+        for plane in planes.iter_mut() {
+            let length = glm::length(&glm::vec3(plane.x, plane.y, plane.z));
+            *plane /= length;
+        }
+        Frustum { planes }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| {
+            glm::dot(&glm::vec3(plane.x, plane.y, plane.z), &sphere.center) + plane.w >= -sphere.radius
+        })
+    }
+
+    // Checks the corner of the box farthest along each plane normal, if even that one is outside the box is too.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let farthest = glm::vec3(
+                if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            glm::dot(&glm::vec3(plane.x, plane.y, plane.z), &farthest) + plane.w >= 0.0
+        })
+    }
+}
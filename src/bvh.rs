@@ -0,0 +1,448 @@
+extern crate nalgebra_glm as glm;
+use crate::bounds;
+use crate::mesh;
+use crate::scene_graph;
+
+// leaves are not split any further once they hold this many triangles
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: glm::Vec3,
+    pub direction: glm::Vec3,
+}
+
+impl Ray {
+    // The direction is normalized, so the distance along the ray is in the same units as the scene.
+    pub fn new(origin: glm::Vec3, direction: glm::Vec3) -> Ray {
+        Ray { origin, direction: glm::normalize(&direction) }
+    }
+
+    pub fn at(&self, distance: f32) -> glm::Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    // The ray moved by the matrix. The direction is left unnormalized, so a distance along the new ray
+    // is the same point as that distance along the old one.
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Ray {
+        let origin = matrix * glm::vec4(self.origin.x, self.origin.y, self.origin.z, 1.0);
+        let direction = matrix * glm::vec4(self.direction.x, self.direction.y, self.direction.z, 0.0);
+        Ray {
+            origin: glm::vec3(origin.x, origin.y, origin.z),
+            direction: glm::vec3(direction.x, direction.y, direction.z),
+        }
+    }
+
+    // Where the ray enters the box, None if it misses it or the box is behind the ray.
+    pub fn intersects_aabb(&self, aabb: &bounds::Aabb) -> Option<f32> {
+        let (mut near, mut far) = (0.0f32, f32::MAX);
+        for axis in 0..3 {
+            let inverse = 1.0 / self.direction[axis];
+            let mut t0 = (aabb.min[axis] - self.origin[axis]) * inverse;
+            let mut t1 = (aabb.max[axis] - self.origin[axis]) * inverse;
+            if inverse < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // a ray parallel to the slab gives NaN when it starts on its edge, max and min skip those
+            near = near.max(t0);
+            far = far.min(t1);
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+// Where a ray hit a mesh.
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub distance: f32,
+    pub triangle: usize,         // the index of the triangle, the corners are indices[triangle * 3..triangle * 3 + 3]
+    pub barycentrics: glm::Vec3, // the weights of the three corners at the hit point
+    pub normal: glm::Vec3,       // of the face, turned towards where the ray came from
+}
+
+struct BvhNode {
+    bounds: bounds::Aabb,
+    // a leaf when count > 0, then first is where its triangles start in Bvh::order,
+    // otherwise the children are at first and first + 1
+    first: usize,
+    count: usize,
+}
+
+// A bounding volume hierarchy over the triangles of a mesh, a tree of boxes that lets a ray skip
+// everything it can't possibly hit.
+pub struct Bvh {
+    vertices: Vec<glm::Vec3>,
+    triangles: Vec<[u32; 3]>,
+    order: Vec<usize>, // the triangles sorted so every leaf owns a run of them
+    nodes: Vec<BvhNode>,
+}
+
+impl Bvh {
+    pub fn new(mesh: &mesh::Mesh) -> Bvh {
+        let vertices: Vec<glm::Vec3> = mesh.vertices.chunks_exact(3).map(|v| glm::vec3(v[0], v[1], v[2])).collect();
+        let triangles: Vec<[u32; 3]> = mesh.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+        let mut bvh = Bvh {
+            order: (0..triangles.len()).collect(),
+            vertices,
+            triangles,
+            nodes: vec![],
+        };
+        if !bvh.triangles.is_empty() {
+            let centers: Vec<glm::Vec3> = bvh.triangles.iter()
+                .map(|t| t.iter().fold(glm::Vec3::zeros(), |sum, &i| sum + bvh.vertices[i as usize]) / 3.0)
+                .collect();
+            bvh.nodes.push(BvhNode { bounds: bvh.bounds(0, bvh.order.len()), first: 0, count: bvh.order.len() });
+            bvh.split(0, &centers);
+        }
+        bvh
+    }
+
+    fn corners(&self, triangle: usize) -> [glm::Vec3; 3] {
+        self.triangles[triangle].map(|i| self.vertices[i as usize])
+    }
+
+    fn bounds(&self, first: usize, count: usize) -> bounds::Aabb {
+        let mut aabb = bounds::Aabb {
+            min: glm::vec3(f32::MAX, f32::MAX, f32::MAX),
+            max: glm::vec3(f32::MIN, f32::MIN, f32::MIN),
+        };
+        for &triangle in &self.order[first..first + count] {
+            for corner in &self.corners(triangle) {
+                aabb.min = glm::min2(&aabb.min, corner);
+                aabb.max = glm::max2(&aabb.max, corner);
+            }
+        }
+        aabb
+    }
+
+    // Halves the node along the longest side of the box around the triangle centers.
+    fn split(&mut self, index: usize, centers: &[glm::Vec3]) {
+        let (first, count) = (self.nodes[index].first, self.nodes[index].count);
+        if count <= MAX_LEAF_TRIANGLES {
+            return;
+        }
+
+        let mut low = glm::vec3(f32::MAX, f32::MAX, f32::MAX);
+        let mut high = glm::vec3(f32::MIN, f32::MIN, f32::MIN);
+        for &triangle in &self.order[first..first + count] {
+            low = glm::min2(&low, &centers[triangle]);
+            high = glm::max2(&high, &centers[triangle]);
+        }
+        let size = high - low;
+        let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+
+        // the median, so the tree stays balanced even when the triangles bunch up
+        let half = count / 2;
+        self.order[first..first + count].select_nth_unstable_by(half, |&a, &b| {
+            centers[a][axis].partial_cmp(&centers[b][axis]).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode { bounds: self.bounds(first, half), first, count: half });
+        self.nodes.push(BvhNode { bounds: self.bounds(first + half, count - half), first: first + half, count: count - half });
+        self.nodes[index].first = left;
+        self.nodes[index].count = 0;
+        self.split(left, centers);
+        self.split(left + 1, centers);
+    }
+
+    // The closest triangle the ray hits within max_distance.
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<RayHit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut closest: Option<RayHit> = None;
+        let mut limit = max_distance;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match ray.intersects_aabb(&node.bounds) {
+                Some(entry) if entry <= limit => (),
+                _ => continue,
+            }
+            if node.count > 0 {
+                for &triangle in &self.order[node.first..node.first + node.count] {
+                    if let Some(hit) = self.raycast_triangle(ray, triangle, limit) {
+                        limit = hit.distance;
+                        closest = Some(hit);
+                    }
+                }
+            } else {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            }
+        }
+        closest
+    }
+
+    // Möller-Trumbore, hitting both sides of the triangle.
+    fn raycast_triangle(&self, ray: &Ray, triangle: usize, max_distance: f32) -> Option<RayHit> {
+        let [a, b, c] = self.corners(triangle);
+        let (edge1, edge2) = (b - a, c - a);
+        let p = glm::cross(&ray.direction, &edge2);
+        let determinant = glm::dot(&edge1, &p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let to_origin = ray.origin - a;
+        let u = glm::dot(&to_origin, &p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = glm::cross(&to_origin, &edge1);
+        let v = glm::dot(&ray.direction, &q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = glm::dot(&edge2, &q) * inverse;
+        if distance < 0.0 || distance > max_distance {
+            return None;
+        }
+
+        let normal = glm::normalize(&glm::cross(&edge1, &edge2));
+        Some(RayHit {
+            distance,
+            triangle,
+            barycentrics: glm::vec3(1.0 - u - v, u, v),
+            normal: if glm::dot(&normal, &ray.direction) > 0.0 { -normal } else { normal },
+        })
+    }
+}
+
+// The closest node a ray hit in the scene graph.
+#[derive(Clone, Copy, Debug)]
+pub struct SceneHit {
+    pub node: *mut scene_graph::SceneNode,
+    pub distance: f32,
+    pub point: glm::Vec3,  // in world space
+    pub normal: glm::Vec3, // in world space
+    pub triangle: usize,
+    pub barycentrics: glm::Vec3,
+}
+
+// Casts a ray in world space against every node with a bvh, and returns the closest hit.
+// Subtrees the ray misses the world bounds of are skipped.
+// Has to be called after the node transformations have been updated.
+pub unsafe fn raycast(root: &mut scene_graph::SceneNode, ray: &Ray, max_distance: f32) -> Option<SceneHit> {
+    let ray = Ray::new(ray.origin, ray.direction);
+    let mut closest = None;
+    raycast_node(root, &ray, max_distance, &mut closest);
+    closest
+}
+
+// Goes down the pointers of the children, so the node of the hit can be changed through.
+unsafe fn raycast_node(node: *mut scene_graph::SceneNode, ray: &Ray, max_distance: f32, closest: &mut Option<SceneHit>) {
+    let root = &*node;
+    let limit = closest.map_or(max_distance, |hit| hit.distance);
+    if let Some(world_bounds) = &root.world_bounds {
+        match ray.intersects_aabb(world_bounds) {
+            Some(entry) if entry <= limit => (),
+            _ => return,
+        }
+    }
+
+    if let Some(bvh) = &root.bvh {
+        // the distances along the local ray are the same as along the world one, see Ray::transformed
+        let inverse = glm::inverse(&root.current_transformation_matrix);
+        if let Some(hit) = bvh.raycast(&ray.transformed(&inverse), limit) {
+            let normal = glm::mat4_to_mat3(&inverse).transpose() * hit.normal;
+            *closest = Some(SceneHit {
+                node,
+                distance: hit.distance,
+                point: ray.at(hit.distance),
+                normal: glm::normalize(&normal),
+                triangle: hit.triangle,
+                barycentrics: hit.barycentrics,
+            });
+        }
+    }
+
+    // Recurse
+    for &child in &root.children {
+        raycast_node(child, ray, max_distance, closest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Random;
+    use std::rc::Rc;
+
+    fn random_vec3(random: &mut Random, scale: f32) -> glm::Vec3 {
+        glm::vec3(random.float() - 0.5, random.float() - 0.5, random.float() - 0.5) * scale
+    }
+
+    // small triangles scattered around a cube, like a messy mesh
+    fn random_mesh(random: &mut Random, triangles: usize) -> mesh::Mesh {
+        let mut vertices = vec![];
+        for _ in 0..triangles {
+            let center = random_vec3(random, 20.0);
+            for _ in 0..3 {
+                let corner = center + random_vec3(random, 4.0);
+                vertices.extend_from_slice(&[corner.x, corner.y, corner.z]);
+            }
+        }
+        let count = vertices.len() / 3;
+        mesh::Mesh::new(vertices, vec![0.0; count * 3], vec![1.0; count * 4], (0..count as u32).collect())
+    }
+
+    // rays from outside the cube, aimed at somewhere inside it so most of them hit something
+    fn random_ray(random: &mut Random) -> Ray {
+        let origin = random_vec3(random, 80.0);
+        let target = random_vec3(random, 20.0);
+        Ray::new(origin, target - origin)
+    }
+
+    fn brute_force(bvh: &Bvh, ray: &Ray, max_distance: f32) -> Option<RayHit> {
+        let mut closest: Option<RayHit> = None;
+        for triangle in 0..bvh.triangles.len() {
+            let limit = closest.map_or(max_distance, |hit| hit.distance);
+            if let Some(hit) = bvh.raycast_triangle(ray, triangle, limit) {
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        let mut random = Random::new(42);
+        let mesh = random_mesh(&mut random, 500);
+        let bvh = Bvh::new(&mesh);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = random_ray(&mut random);
+            let expected = brute_force(&bvh, &ray, f32::MAX);
+            let actual = bvh.raycast(&ray, f32::MAX);
+            match (expected, actual) {
+                (None, None) => (),
+                (Some(expected), Some(actual)) => {
+                    hits += 1;
+                    assert!((expected.distance - actual.distance).abs() < 1e-4);
+                    assert!((actual.barycentrics.x + actual.barycentrics.y + actual.barycentrics.z - 1.0).abs() < 1e-4);
+                    assert!(glm::dot(&actual.normal, &ray.direction) <= 0.0);
+                    // the same point, even if two triangles happen to meet there
+                    assert!(glm::distance(&ray.at(expected.distance), &ray.at(actual.distance)) < 1e-3);
+                }
+                (expected, actual) => panic!("brute force found {:?}, the bvh found {:?}", expected, actual),
+            }
+        }
+        assert!(hits > 100, "only {} of the rays hit anything", hits);
+    }
+
+    #[test]
+    fn bvh_respects_max_distance() {
+        let mut random = Random::new(7);
+        let mesh = random_mesh(&mut random, 200);
+        let bvh = Bvh::new(&mesh);
+
+        for _ in 0..500 {
+            let ray = random_ray(&mut random);
+            let max_distance = random.float() * 100.0;
+            let expected = brute_force(&bvh, &ray, max_distance).map(|hit| hit.triangle);
+            let actual = bvh.raycast(&ray, max_distance);
+            assert!(actual.is_none_or(|hit| hit.distance <= max_distance));
+            assert_eq!(expected.is_some(), actual.is_some());
+        }
+    }
+
+    #[test]
+    fn barycentrics_give_the_hit_point() {
+        let mut random = Random::new(3);
+        let mesh = random_mesh(&mut random, 100);
+        let bvh = Bvh::new(&mesh);
+
+        for _ in 0..500 {
+            let ray = random_ray(&mut random);
+            if let Some(hit) = bvh.raycast(&ray, f32::MAX) {
+                let [a, b, c] = bvh.corners(hit.triangle);
+                let point = a * hit.barycentrics.x + b * hit.barycentrics.y + c * hit.barycentrics.z;
+                assert!(glm::distance(&point, &ray.at(hit.distance)) < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_mesh_is_never_hit() {
+        let bvh = Bvh::new(&mesh::Mesh::new(vec![], vec![], vec![], vec![]));
+        assert!(bvh.raycast(&Ray::new(glm::zero(), glm::vec3(0.0, 0.0, -1.0)), f32::MAX).is_none());
+    }
+
+    #[test]
+    fn scene_raycast_matches_brute_force() {
+        let mut random = Random::new(11);
+        let meshes: Vec<mesh::Mesh> = (0..4).map(|_| random_mesh(&mut random, 50)).collect();
+        let bvhs: Vec<Rc<Bvh>> = meshes.iter().map(|mesh| Rc::new(Bvh::new(mesh))).collect();
+
+        unsafe {
+            // a root with a chain of moved, turned and scaled children under it, turning around points off their origins
+            let mut root = scene_graph::SceneNode::new();
+            let mut nodes: Vec<scene_graph::Node> = vec![];
+            for (i, mesh) in meshes.iter().enumerate() {
+                let mut node = scene_graph::SceneNode::from_vao(0, mesh.index_count, format!("node_{}", i));
+                node.set_bounds(mesh);
+                node.bvh = Some(bvhs[i].clone());
+                node.position = random_vec3(&mut random, 30.0);
+                node.rotation = random_vec3(&mut random, 3.0);
+                node.scale = glm::vec3(1.0, 1.0, 1.0) * (0.5 + random.float());
+                node.reference_point = random_vec3(&mut random, 5.0);
+                nodes.push(node);
+            }
+            for i in 1..nodes.len() {
+                let (parents, children) = nodes.split_at_mut(i);
+                parents[i - 1].add_child(&children[0]);
+            }
+            root.add_child(&nodes[0]);
+
+            // placed the way the main loop does it, the parents' bounds covering the children
+            crate::update_node_transformations(&mut root, &glm::identity());
+
+            // every triangle moved into world space and put in one mesh, to check against
+            let mut world_vertices = vec![];
+            let mut owners = vec![];
+            for (i, node) in nodes.iter().enumerate() {
+                for triangle in 0..bvhs[i].triangles.len() {
+                    for corner in &bvhs[i].corners(triangle) {
+                        let world = node.current_transformation_matrix * glm::vec4(corner.x, corner.y, corner.z, 1.0);
+                        world_vertices.extend_from_slice(&[world.x, world.y, world.z]);
+                    }
+                    owners.push(i);
+                }
+            }
+            let count = world_vertices.len() / 3;
+            let world_mesh = mesh::Mesh::new(world_vertices, vec![0.0; count * 3], vec![1.0; count * 4], (0..count as u32).collect());
+            let world_bvh = Bvh::new(&world_mesh);
+            let centers: Vec<glm::Vec3> = nodes.iter().map(|node| {
+                let center = node.current_transformation_matrix * glm::vec4(0.0, 0.0, 0.0, 1.0);
+                glm::vec3(center.x, center.y, center.z)
+            }).collect();
+
+            let mut hits = 0;
+            for i in 0..1000 {
+                // aimed near one of the nodes, so most of them hit something
+                let origin = random_vec3(&mut random, 200.0);
+                let target = centers[i % centers.len()] + random_vec3(&mut random, 30.0);
+                let ray = Ray::new(origin, target - origin);
+                let expected = brute_force(&world_bvh, &ray, f32::MAX).map(|hit| (owners[hit.triangle], hit.distance));
+
+                let actual = raycast(&mut root, &ray, f32::MAX);
+                match (expected, actual) {
+                    (None, None) => (),
+                    (Some((node, distance)), Some(hit)) => {
+                        hits += 1;
+                        assert!((distance - hit.distance).abs() < 1e-2 * distance.max(1.0), "{} != {}", distance, hit.distance);
+                        assert_eq!((*hit.node).name, format!("node_{}", node));
+                    }
+                    (expected, actual) => panic!("brute force found {:?}, the scene raycast found {:?}", expected, actual.map(|hit| hit.distance)),
+                }
+            }
+            assert!(hits > 100, "only {} of the rays hit anything", hits);
+        }
+    }
+}
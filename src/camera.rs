@@ -21,6 +21,10 @@ use std::{
 //init a public struct called camera.
 pub struct Camera {
     pub m_perspective: glm::Mat4,
+    pub aspect: f32,
+    pub fov: f32,
+    pub z_near: f32,
+    pub z_far: f32,
     m_rotation :  Box<glm::Mat4>, // the variabels which holds the rotation matrix
     m_translation :  Box<glm::Mat4> // the variable which holds the translation matrix.
 }
@@ -33,6 +37,7 @@ impl Camera{
     pub unsafe fn new(aspect : f32, fov : f32, z_near : f32, z_far: f32, offset: f32) -> Camera {
         Camera { 
             m_perspective: glm::perspective(aspect, fov, z_near, z_far), // create the perspective matrix for task 4 [report]
+            aspect, fov, z_near, z_far, // kept so other passes (like the shadows) can rebuild parts of the frustum
             m_rotation:  Box::new(glm::identity()), // initalizing the rotation mattrix with an identity matrix.
             m_translation:  Box::new(translate(0.0, -17.0, offset)) // init the translation matrix with an offset. ( comon is -5.0 f32)
          }
@@ -74,6 +79,22 @@ impl Camera{
        return  self.m_perspective * self.m_rotation.as_ref() * self.m_translation.as_ref() 
     }
 
+    // the camera matrix without the perspective, which moves world space into view space.
+    pub unsafe fn view_matrix(&self) -> glm::Mat4 {
+        self.m_rotation.as_ref() * self.m_translation.as_ref()
+    }
+
+    // where the camera is in world space.
+    pub unsafe fn position(&self) -> glm::Vec3 {
+        let position = glm::inverse(&self.view_matrix()) * glm::vec4(0.0, 0.0, 0.0, 1.0);
+        glm::vec3(position.x, position.y, position.z)
+    }
+
+    // the camera matrix with only the rotation, used by the skybox so it never gets any closer.
+    pub unsafe fn sky_matrix(&self) -> glm::Mat4 {
+        self.m_perspective * self.m_rotation.as_ref()
+    }
+
 }
 
 // ---------------------- Helper functions ----------------------
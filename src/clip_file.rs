@@ -0,0 +1,229 @@
+extern crate nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+
+use crate::animation;
+
+// Animation clips written outside of Rust, as JSON or RON. Both look the same apart from the syntax:
+//
+//     (
+//         name: "door",
+//         tracks: [
+//             (
+//                 target: "body/door",
+//                 property: Position,
+//                 interpolation: CubicBezier,
+//                 keyframes: [
+//                     (time: 0.0, value: (0.0, 0.0, 0.0)),
+//                     (time: 1.2, value: (0.0, 0.0, 2.0), in_tangent: (0.0, 0.0, 0.5)),
+//                 ],
+//             ),
+//         ],
+//     )
+//
+// The tangents can be left out, and are flat when they are.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Json,
+    Ron,
+}
+
+impl Format {
+    // By the extension of the file.
+    pub fn from_path(path: &str) -> Result<Format, String> {
+        match std::path::Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Ok(Format::Json),
+            Some("ron") => Ok(Format::Ron),
+            _ => Err(format!("Don't know the format of {}, expected a .json or .ron file", path)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ClipData {
+    name: String,
+    tracks: Vec<TrackData>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TrackData {
+    target: String,
+    property: animation::Property,
+    interpolation: animation::Interpolation,
+    keyframes: Vec<KeyframeData>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyframeData {
+    time: f32,
+    value: (f32, f32, f32),
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    in_tangent: Option<(f32, f32, f32)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    out_tangent: Option<(f32, f32, f32)>,
+}
+
+// the tangents are written without Some() around them
+fn ron_options() -> ron::Options {
+    ron::Options::default().with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+}
+
+fn to_vec3(value: (f32, f32, f32)) -> glm::Vec3 {
+    glm::vec3(value.0, value.1, value.2)
+}
+
+// Flat tangents are left out of the file.
+fn from_tangent(tangent: &glm::Vec3) -> Option<(f32, f32, f32)> {
+    if *tangent == glm::zero::<glm::Vec3>() {
+        None
+    } else {
+        Some((tangent.x, tangent.y, tangent.z))
+    }
+}
+
+pub fn load(path: &str) -> Result<animation::AnimationClip, String> {
+    let format = Format::from_path(path)?;
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    parse(&text, format).map_err(|e| format!("{}: {}", path, e))
+}
+
+pub fn parse(text: &str, format: Format) -> Result<animation::AnimationClip, String> {
+    let data: ClipData = match format {
+        Format::Json => serde_json::from_str(text).map_err(|e| e.to_string())?,
+        Format::Ron => ron_options().from_str(text).map_err(|e| e.to_string())?,
+    };
+
+    let mut clip = animation::AnimationClip::new(&data.name);
+    for track in data.tracks {
+        // the player counts on every track having somewhere to start, and on time running forwards
+        if track.keyframes.is_empty() {
+            return Err(format!("Clip {}: the track of {} has no keyframes", data.name, track.target));
+        }
+        if let Some(keyframe) = track.keyframes.iter().find(|keyframe| !(keyframe.time >= 0.0 && keyframe.time.is_finite())) {
+            return Err(format!("Clip {}: the track of {} has a keyframe at {}", data.name, track.target, keyframe.time));
+        }
+        let keyframes = track.keyframes.iter().map(|keyframe| {
+            animation::Keyframe::new(keyframe.time, to_vec3(keyframe.value)).with_tangents(
+                keyframe.in_tangent.map_or(glm::zero(), to_vec3),
+                keyframe.out_tangent.map_or(glm::zero(), to_vec3),
+            )
+        }).collect();
+        clip = clip.with_track(animation::Track::new(&track.target, track.property, track.interpolation, keyframes));
+    }
+    Ok(clip)
+}
+
+pub fn export(clip: &animation::AnimationClip, format: Format) -> String {
+    let data = ClipData {
+        name: clip.name.clone(),
+        tracks: clip.tracks.iter().map(|track| TrackData {
+            target: track.target.clone(),
+            property: track.property,
+            interpolation: track.interpolation,
+            keyframes: track.keyframes.iter().map(|keyframe| KeyframeData {
+                time: keyframe.time,
+                value: (keyframe.value.x, keyframe.value.y, keyframe.value.z),
+                in_tangent: from_tangent(&keyframe.in_tangent),
+                out_tangent: from_tangent(&keyframe.out_tangent),
+            }).collect(),
+        }).collect(),
+    };
+    match format {
+        Format::Json => serde_json::to_string_pretty(&data).expect("Failed to write the clip as JSON"),
+        Format::Ron => ron_options().to_string_pretty(&data, ron::ser::PrettyConfig::default()).expect("Failed to write the clip as RON"),
+    }
+}
+
+pub fn save(clip: &animation::AnimationClip, path: &str) -> Result<(), String> {
+    let format = Format::from_path(path)?;
+    std::fs::write(path, export(clip, format)).map_err(|e| format!("Couldn't write {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(with_tangents: bool) -> animation::AnimationClip {
+        let mut eased = animation::Keyframe::new(1.5, glm::vec3(0.0, 0.25, -2.0));
+        if with_tangents {
+            eased = eased.with_tangents(glm::vec3(0.0, 0.5, 0.0), glm::vec3(1.0, 0.0, -0.75));
+        }
+        animation::AnimationClip::new("door")
+            .with_track(animation::Track::new("body/door", animation::Property::Position, animation::Interpolation::CubicBezier, vec![
+                animation::Keyframe::new(0.0, glm::vec3(1.0, 2.0, 3.0)),
+                eased,
+            ]))
+            .with_track(animation::Track::new("main", animation::Property::Rotation, animation::Interpolation::Step, vec![
+                animation::Keyframe::new(0.0, glm::zero()),
+                animation::Keyframe::new(0.5, glm::vec3(0.1, std::f32::consts::TAU, 0.0)),
+            ]))
+    }
+
+    fn assert_same(a: &animation::AnimationClip, b: &animation::AnimationClip) {
+        assert_eq!(a.name, b.name);
+        assert_eq!(a.tracks.len(), b.tracks.len());
+        for (a, b) in a.tracks.iter().zip(b.tracks.iter()) {
+            assert_eq!(a.target, b.target);
+            assert_eq!(a.property, b.property);
+            assert_eq!(a.interpolation, b.interpolation);
+            assert_eq!(a.keyframes.len(), b.keyframes.len());
+            for (a, b) in a.keyframes.iter().zip(b.keyframes.iter()) {
+                assert_eq!(a.time, b.time);
+                assert_eq!(a.value, b.value);
+                assert_eq!(a.in_tangent, b.in_tangent);
+                assert_eq!(a.out_tangent, b.out_tangent);
+            }
+        }
+    }
+
+    fn round_trip(format: Format, with_tangents: bool) {
+        let clip = clip(with_tangents);
+        let text = export(&clip, format);
+        // flat tangents are left out of the file
+        assert_eq!(text.contains("in_tangent"), with_tangents);
+        assert_same(&clip, &parse(&text, format).unwrap());
+    }
+
+    #[test]
+    fn json_round_trips() {
+        round_trip(Format::Json, false);
+        round_trip(Format::Json, true);
+    }
+
+    #[test]
+    fn ron_round_trips() {
+        round_trip(Format::Ron, false);
+        round_trip(Format::Ron, true);
+    }
+
+    #[test]
+    fn tracks_without_keyframes_are_refused() {
+        let json = r#"{"name": "empty", "tracks": [{"target": "door", "property": "Position", "interpolation": "Linear", "keyframes": []}]}"#;
+        let ron = "(name: \"empty\", tracks: [(target: \"door\", property: Position, interpolation: Linear, keyframes: [])])";
+        for (text, format) in [(json, Format::Json), (ron, Format::Ron)] {
+            assert_eq!(parse(text, format).err().unwrap(), "Clip empty: the track of door has no keyframes");
+        }
+    }
+
+    #[test]
+    fn keyframes_before_the_start_are_refused() {
+        let json = r#"{"name": "early", "tracks": [{"target": "door", "property": "Position", "interpolation": "Linear",
+            "keyframes": [{"time": -0.5, "value": [0.0, 0.0, 0.0]}, {"time": 1.0, "value": [0.0, 0.0, 2.0]}]}]}"#;
+        let ron = "(name: \"early\", tracks: [(target: \"door\", property: Position, interpolation: Linear,
+            keyframes: [(time: -0.5, value: (0.0, 0.0, 0.0)), (time: 1.0, value: (0.0, 0.0, 2.0))])])";
+        for (text, format) in [(json, Format::Json), (ron, Format::Ron)] {
+            assert_eq!(parse(text, format).err().unwrap(), "Clip early: the track of door has a keyframe at -0.5");
+        }
+    }
+
+    #[test]
+    fn shipped_clips_load() {
+        for entry in std::fs::read_dir("./resources/animations").unwrap() {
+            let path = entry.unwrap().path();
+            let path = path.to_str().unwrap();
+            if let Err(e) = load(path) {
+                panic!("{}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// the slowest and fastest the simulation can be made to run
+const MIN_TIME_SCALE: f32 = 1.0 / 16.0;
+const MAX_TIME_SCALE: f32 = 8.0;
+
+// Owns the simulation time. The wall clock time of every frame goes through it before the game loop sees it,
+// so pausing, slow motion and fast-forward reach everything the simulation steps move, the rotors and the
+// zombie paths included.
+pub struct Clock {
+    pub paused: bool,
+    pub time_scale: f32,
+    time: f64, // seconds simulated since the start, in f64 so the steps don't get lost once it grows large
+    step_requested: bool,
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock {
+            paused: false,
+            time_scale: 1.0,
+            time: 0.0,
+            step_requested: false,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+    }
+
+    // Lets exactly one step through on the next frame while paused.
+    pub fn request_step(&mut self) {
+        if self.paused {
+            self.step_requested = true;
+        }
+    }
+
+    // The time the game loop should simulate for a frame that took delta_time on the wall clock.
+    pub fn frame_delta(&mut self, delta_time: f32, timestep: f32) -> f32 {
+        if !self.paused {
+            return delta_time * self.time_scale;
+        }
+        if self.step_requested {
+            self.step_requested = false;
+            return timestep;
+        }
+        0.0
+    }
+
+    // Called once per simulation step.
+    pub fn advance(&mut self, timestep: f32) {
+        self.time += timestep as f64;
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time as f32
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new()
+    }
+}
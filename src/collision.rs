@@ -0,0 +1,216 @@
+extern crate nalgebra_glm as glm;
+use std::collections::HashSet;
+
+use crate::bounds;
+use crate::scene_graph;
+use crate::terrain_sampler;
+
+// An oriented bounding box, a box that turns along with the node it belongs to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Obb {
+    pub center: glm::Vec3,
+    pub axes: [glm::Vec3; 3], // unit length, at right angles to each other
+    pub half_extents: glm::Vec3,
+}
+
+impl Obb {
+    // The box moved into world space by the matrix, which may scale but not shear it.
+    pub fn from_aabb(aabb: &bounds::Aabb, matrix: &glm::Mat4) -> Obb {
+        let center = matrix * glm::vec4(aabb.center().x, aabb.center().y, aabb.center().z, 1.0);
+        let rotation = glm::mat4_to_mat3(matrix);
+        let columns = [0, 1, 2].map(|i| rotation.column(i).into_owned());
+        let scale = glm::vec3(glm::length(&columns[0]), glm::length(&columns[1]), glm::length(&columns[2]));
+        Obb {
+            center: glm::vec3(center.x, center.y, center.z),
+            axes: [0, 1, 2].map(|i| columns[i] / scale[i].max(1e-6)),
+            half_extents: aabb.extents().component_mul(&scale),
+        }
+    }
+
+    pub fn corners(&self) -> [glm::Vec3; 8] {
+        let mut corners = [self.center; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            for axis in 0..3 {
+                let sign = if i & (1 << axis) == 0 { -1.0 } else { 1.0 };
+                *corner += self.axes[axis] * self.half_extents[axis] * sign;
+            }
+        }
+        corners
+    }
+
+    pub fn bounding_sphere(&self) -> bounds::BoundingSphere {
+        bounds::BoundingSphere { center: self.center, radius: glm::length(&self.half_extents) }
+    }
+
+    // The point in the box closest to the given one.
+    pub fn closest_point(&self, point: &glm::Vec3) -> glm::Vec3 {
+        let offset = point - self.center;
+        let mut closest = self.center;
+        for axis in 0..3 {
+            let distance = glm::dot(&offset, &self.axes[axis]).clamp(-self.half_extents[axis], self.half_extents[axis]);
+            closest += self.axes[axis] * distance;
+        }
+        closest
+    }
+
+    // Separating axis test. When the boxes overlap, gives the direction from other towards self that
+    // pushes them apart the shortest way, and how far they have to go.
+    pub fn intersects(&self, other: &Obb) -> Option<(glm::Vec3, f32)> {
+        let between = other.center - self.center;
+        let mut axes: Vec<glm::Vec3> = self.axes.iter().chain(other.axes.iter()).cloned().collect();
+        for a in &self.axes {
+            for b in &other.axes {
+                axes.push(glm::cross(a, b));
+            }
+        }
+
+        let mut best: Option<(glm::Vec3, f32)> = None;
+        for axis in axes {
+            // parallel edges give no axis to test
+            let length = glm::length(&axis);
+            if length < 1e-6 {
+                continue;
+            }
+            let axis = axis / length;
+            let reach = |obb: &Obb| (0..3).map(|i| glm::dot(&obb.axes[i], &axis).abs() * obb.half_extents[i]).sum::<f32>();
+            let distance = glm::dot(&between, &axis);
+            let overlap = reach(self) + reach(other) - distance.abs();
+            if overlap < 0.0 {
+                return None;
+            }
+            if best.is_none_or(|(_, penetration)| overlap < penetration) {
+                // turned to point away from other
+                best = Some((if distance > 0.0 { -axis } else { axis }, overlap));
+            }
+        }
+        best
+    }
+}
+
+// A node taking part in collisions. The box covers the node and everything under it, like the rotors of a helicopter.
+pub struct Collider {
+    pub node: *mut scene_graph::SceneNode,
+    pub obb: Obb,
+    pub sphere: bounds::BoundingSphere,
+    pub against_terrain: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ContactWith {
+    Collider(usize),
+    Terrain,
+}
+
+// Two things touching. Moving collider a along the normal by the penetration separates them.
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+    pub a: usize, // index into CollisionWorld::colliders
+    pub b: ContactWith,
+    pub point: glm::Vec3,  // in world space, roughly in the middle of the overlap
+    pub normal: glm::Vec3, // pointing from b towards a
+    pub penetration: f32,
+    pub started: bool, // the two were not touching the frame before
+}
+
+#[derive(Default)]
+pub struct CollisionWorld {
+    pub colliders: Vec<Collider>,
+    pub contacts: Vec<Contact>,
+    touching: HashSet<(usize, ContactWith)>,
+}
+
+impl CollisionWorld {
+    pub fn new() -> CollisionWorld {
+        CollisionWorld::default()
+    }
+
+    // Returns the index the contacts will use for the node.
+    pub fn add(&mut self, node: &scene_graph::SceneNode, against_terrain: bool) -> usize {
+        let obb = Obb { center: glm::zero(), axes: [glm::Vec3::x(), glm::Vec3::y(), glm::Vec3::z()], half_extents: glm::zero() };
+        self.colliders.push(Collider {
+            node: node as *const scene_graph::SceneNode as *mut scene_graph::SceneNode,
+            obb,
+            sphere: obb.bounding_sphere(),
+            against_terrain,
+        });
+        self.colliders.len() - 1
+    }
+
+    // Finds everything touching this frame, and keeps it in contacts.
+    // Has to be called after the node transformations have been updated.
+    pub unsafe fn detect(&mut self, terrain: Option<&terrain_sampler::TerrainSampler>) -> &[Contact] {
+        for collider in self.colliders.iter_mut() {
+            let node = &*collider.node;
+            collider.obb = Obb::from_aabb(&subtree_bounds(node), &node.current_transformation_matrix);
+            collider.sphere = collider.obb.bounding_sphere();
+        }
+
+        let mut contacts = vec![];
+        for a in 0..self.colliders.len() {
+            for b in a + 1..self.colliders.len() {
+                let (first, second) = (&self.colliders[a], &self.colliders[b]);
+                // the spheres are a cheap way to skip the pairs that are nowhere near each other
+                if glm::distance(&first.sphere.center, &second.sphere.center) > first.sphere.radius + second.sphere.radius {
+                    continue;
+                }
+                if let Some((normal, penetration)) = first.obb.intersects(&second.obb) {
+                    let point = (first.obb.closest_point(&second.obb.center) + second.obb.closest_point(&first.obb.center)) * 0.5;
+                    contacts.push(Contact { a, b: ContactWith::Collider(b), point, normal, penetration, started: false });
+                }
+            }
+            if let (Some(terrain), true) = (terrain, self.colliders[a].against_terrain) {
+                if let Some(contact) = terrain_contact(a, &self.colliders[a].obb, terrain) {
+                    contacts.push(contact);
+                }
+            }
+        }
+
+        let touching: HashSet<(usize, ContactWith)> = contacts.iter().map(|contact| (contact.a, contact.b)).collect();
+        for contact in contacts.iter_mut() {
+            contact.started = !self.touching.contains(&(contact.a, contact.b));
+        }
+        self.touching = touching;
+        self.contacts = contacts;
+        &self.contacts
+    }
+}
+
+// The box around the mesh of the node and all of its children, in the local space of the node.
+unsafe fn subtree_bounds(root: &scene_graph::SceneNode) -> bounds::Aabb {
+    let to_local = glm::inverse(&root.current_transformation_matrix);
+    let mut aabb: Option<bounds::Aabb> = None;
+    let mut stack: Vec<&scene_graph::SceneNode> = vec![root];
+    while let Some(node) = stack.pop() {
+        if let Some(bounding_box) = &node.bounding_box {
+            let local = bounding_box.transformed(&(to_local * node.current_transformation_matrix));
+            aabb = Some(aabb.map_or(local, |aabb| aabb.union(&local)));
+        }
+        stack.extend(node.children.iter().map(|&child| &*child));
+    }
+    aabb.unwrap_or(bounds::Aabb { min: glm::zero(), max: glm::zero() })
+}
+
+// The corner of the box the farthest under the ground, if any of them are.
+fn terrain_contact(index: usize, obb: &Obb, terrain: &terrain_sampler::TerrainSampler) -> Option<Contact> {
+    let mut deepest: Option<Contact> = None;
+    for corner in obb.corners().iter() {
+        let ground = match terrain.height_at(corner.x, corner.z) {
+            Some(ground) if ground > corner.y => ground,
+            _ => continue,
+        };
+        let normal = terrain.normal_at(corner.x, corner.z).unwrap_or_else(glm::Vec3::y);
+        // the depth straight down, turned into the depth along the slope
+        let penetration = (ground - corner.y) * normal.y;
+        if deepest.is_none_or(|contact| penetration > contact.penetration) {
+            deepest = Some(Contact {
+                a: index,
+                b: ContactWith::Terrain,
+                point: glm::vec3(corner.x, ground, corner.z),
+                normal,
+                penetration,
+                started: false,
+            });
+        }
+    }
+    deepest
+}
@@ -0,0 +1,185 @@
+extern crate nalgebra_glm as glm;
+use crate::framebuffer;
+use crate::light;
+use crate::postprocess;
+use crate::shader;
+use crate::VAO;
+
+// texture units of the G-buffer during the lighting passes, after the shadow map and the environment map
+pub const GBUFFER_TEXTURE_UNIT: u32 = 2;
+
+// How the scene is lit, picked once at startup with --renderer=<path>.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RenderPath {
+    Forward,  // every fragment loops over all the lights while the scene is drawn
+    Deferred, // the scene is drawn into a G-buffer first, and lit afterwards
+}
+
+impl RenderPath {
+    pub fn parse(value: &str) -> Result<RenderPath, String> {
+        match value {
+            "forward" => Ok(RenderPath::Forward),
+            "deferred" => Ok(RenderPath::Deferred),
+            _ => Err(format!("Unknown renderer {}, try forward or deferred", value)),
+        }
+    }
+
+    // Looks for --renderer=<path> among the command line arguments, defaulting to forward.
+    pub fn from_args() -> RenderPath {
+        for arg in std::env::args().skip(1) {
+            if let Some(value) = arg.strip_prefix("--renderer=") {
+                match RenderPath::parse(value) {
+                    Ok(path) => return path,
+                    Err(e) => println!("{}, falling back to forward.", e),
+                }
+            }
+        }
+        RenderPath::Forward
+    }
+}
+
+// Deferred shading. The geometry pass writes albedo, normals, material parameters and depth into the G-buffer,
+// then a full-screen pass adds the directional lights, and every point and spot light is drawn as a sphere
+// covering its range, so it only costs anything on the pixels it can actually reach.
+pub struct DeferredRenderer {
+    pub gbuffer: framebuffer::Framebuffer,
+    pub geometry_shader: shader::Shader,
+    pub directional_shader: shader::Shader,
+    pub volume_shader: shader::Shader,
+    sphere_vao: u32,
+    sphere_index_count: i32,
+    fullscreen_vao: u32,
+}
+
+impl DeferredRenderer {
+    pub unsafe fn new(width: i32, height: i32) -> DeferredRenderer {
+        DeferredRenderer {
+            // albedo, world space normal, material parameters (specular, shininess, emission). The albedo is stored
+            // as sRGB, so the 8 bits are spent where the eye can tell the dark colors apart, like the textures do
+            gbuffer: framebuffer::Framebuffer::new(width, height, &[gl::SRGB8_ALPHA8, gl::RGBA16F, gl::RGBA8], true),
+            geometry_shader: shader::ShaderBuilder::new().attach_file("./shaders/gbuffer.frag").attach_file("./shaders/simple.vert").link(),
+            directional_shader: shader::ShaderBuilder::new()
+                .attach_file("./shaders/fullscreen.vert")
+                .attach_file("./shaders/deferred_directional.frag")
+                .attach_file("./shaders/lighting.frag")
+                .link(),
+            volume_shader: shader::ShaderBuilder::new()
+                .attach_file("./shaders/deferred_light_volume.vert")
+                .attach_file("./shaders/deferred_light_volume.frag")
+                .attach_file("./shaders/lighting.frag")
+                .link(),
+            sphere_vao: 0,
+            sphere_index_count: 0,
+            fullscreen_vao: postprocess::fullscreen_vao(),
+        }.with_sphere(16, 12)
+    }
+
+    unsafe fn with_sphere(mut self, slices: u32, stacks: u32) -> DeferredRenderer {
+        let (vertices, indices) = sphere(slices, stacks);
+        let mut vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut vao_id);
+        gl::BindVertexArray(vao_id);
+
+        let mut buffer_id = 0;
+        VAO::buffer(&mut buffer_id, &vertices, gl::ARRAY_BUFFER, 0, 3);
+        VAO::buffer(&mut buffer_id, &indices, gl::ELEMENT_ARRAY_BUFFER, 0, 0);
+
+        self.sphere_vao = vao_id;
+        self.sphere_index_count = indices.len() as i32;
+        self
+    }
+
+    // Binds and clears the G-buffer, and activates the geometry shader. Draw the scene right after.
+    pub unsafe fn begin_geometry(&self) {
+        self.gbuffer.bind();
+        gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        // the G-buffer holds surfaces, not colors, so nothing may be blended into it
+        gl::Disable(gl::BLEND);
+        // the linear albedo is encoded when it is written, and decoded again when the lighting passes sample it
+        gl::Enable(gl::FRAMEBUFFER_SRGB);
+        self.geometry_shader.activate();
+    }
+
+    // Points the G-buffer samplers of a lighting shader at their textures.
+    unsafe fn bind_gbuffer(&self, shader: &shader::Shader, inverse_view_projection: &glm::Mat4) {
+        let names = ["gbuffer_albedo", "gbuffer_normal", "gbuffer_material"];
+        for (i, name) in names.iter().enumerate() {
+            let unit = GBUFFER_TEXTURE_UNIT + i as u32;
+            self.gbuffer.bind_color(i, unit);
+            gl::Uniform1i(shader.get_uniform_location(name), unit as i32);
+        }
+        let depth_unit = GBUFFER_TEXTURE_UNIT + names.len() as u32;
+        self.gbuffer.bind_depth(depth_unit);
+        gl::Uniform1i(shader.get_uniform_location("gbuffer_depth"), depth_unit as i32);
+        gl::UniformMatrix4fv(shader.get_uniform_location("inverse_view_projection"), 1, gl::FALSE, inverse_view_projection.as_ptr());
+    }
+
+    // Lights the directional part of the G-buffer into the bound framebuffer.
+    // The lights, shadows and fog uniforms of directional_shader have to be uploaded before.
+    pub unsafe fn light_directional(&self, view_projection: &glm::Mat4) {
+        // blending was turned off for the geometry pass, the full-screen pass is opaque anyway
+        gl::Enable(gl::BLEND);
+        gl::Disable(gl::FRAMEBUFFER_SRGB);
+        self.directional_shader.activate();
+        self.bind_gbuffer(&self.directional_shader, &glm::inverse(view_projection));
+        postprocess::draw_fullscreen(self.fullscreen_vao);
+    }
+
+    // Adds every point and spot light on top of the bound framebuffer, one sphere each.
+    // The lights and fog uniforms of volume_shader have to be uploaded before.
+    pub unsafe fn light_volumes(&self, lights: &[light::WorldLight], view_projection: &glm::Mat4) {
+        self.volume_shader.activate();
+        self.bind_gbuffer(&self.volume_shader, &glm::inverse(view_projection));
+        gl::Uniform2f(self.volume_shader.get_uniform_location("screen_size"), self.gbuffer.width as f32, self.gbuffer.height as f32);
+
+        // drawing the back faces without a depth test lights the pixels even with the camera inside the sphere
+        gl::Disable(gl::DEPTH_TEST);
+        gl::DepthMask(gl::FALSE);
+        gl::CullFace(gl::FRONT);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::ONE, gl::ONE);
+        gl::BindVertexArray(self.sphere_vao);
+
+        for (i, light) in lights.iter().take(light::MAX_LIGHTS).enumerate() {
+            if light.kind == light::LightKind::Directional {
+                continue;
+            }
+            // a little bigger than the range, the sphere is made of flat triangles which cut inside of it
+            let model = glm::translation(&light.position) * glm::scaling(&glm::vec3(1.0, 1.0, 1.0).scale(light.range * 1.1));
+            gl::UniformMatrix4fv(3, 1, gl::FALSE, (view_projection * model).as_ptr());
+            gl::Uniform1i(self.volume_shader.get_uniform_location("light_index"), i as i32);
+            gl::DrawElements(gl::TRIANGLES, self.sphere_index_count, gl::UNSIGNED_INT, std::ptr::null());
+        }
+
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::CullFace(gl::BACK);
+        gl::DepthMask(gl::TRUE);
+        gl::Enable(gl::DEPTH_TEST);
+    }
+}
+
+// A unit sphere made of latitude and longitude lines, as positions and triangle indices.
+pub fn sphere(slices: u32, stacks: u32) -> (Vec<f32>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(((slices + 1) * (stacks + 1) * 3) as usize);
+    for stack in 0..=stacks {
+        let phi = std::f32::consts::PI * stack as f32 / stacks as f32;
+        for slice in 0..=slices {
+            let theta = 2.0 * std::f32::consts::PI * slice as f32 / slices as f32;
+            vertices.push(phi.sin() * theta.cos());
+            vertices.push(phi.cos());
+            vertices.push(phi.sin() * theta.sin());
+        }
+    }
+
+    let mut indices = Vec::with_capacity((slices * stacks * 6) as usize);
+    for stack in 0..stacks {
+        for slice in 0..slices {
+            let a = stack * (slices + 1) + slice;
+            let b = a + slices + 1;
+            // counter clockwise seen from outside
+            indices.extend_from_slice(&[a, a + 1, b, b, a + 1, b + 1]);
+        }
+    }
+    (vertices, indices)
+}
@@ -0,0 +1,71 @@
+extern crate nalgebra_glm as glm;
+use crate::shader;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FogMode {
+    Linear,      // ramps up from start to end
+    Exponential, // thickens with distance by density
+    Height,      // exponential, but thins out with height above base_height
+}
+
+// Scene settings for the distance fog in the main shader.
+// The fog fades toward the skybox when there is one, and toward the plain color otherwise.
+pub struct Fog {
+    pub enabled: bool,
+    pub mode: FogMode,
+    pub color: glm::Vec3,
+    pub density: f32,
+    pub start: f32,
+    pub end: f32,
+    pub base_height: f32,    // height fog is densest below this height
+    pub height_falloff: f32, // how quickly height fog thins out above base_height
+    pub use_environment: bool,
+}
+
+impl Fog {
+    pub fn new(mode: FogMode, color: glm::Vec3) -> Fog {
+        Fog {
+            enabled: true,
+            mode,
+            color,
+            density: 0.004,
+            start: 150.0,
+            end: 900.0,
+            base_height: 0.0,
+            height_falloff: 0.05,
+            use_environment: true,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    // Goes through the fog modes, one step each call.
+    pub fn next_mode(&mut self) {
+        self.mode = match self.mode {
+            FogMode::Linear => FogMode::Exponential,
+            FogMode::Exponential => FogMode::Height,
+            FogMode::Height => FogMode::Linear,
+        };
+    }
+
+    // Sends the fog settings to the active shader. The camera position is needed for the distance and height of each fragment.
+    pub unsafe fn upload(&self, shader: &shader::Shader, camera_position: &glm::Vec3, has_environment: bool) {
+        let mode = match self.mode {
+            FogMode::Linear => 0,
+            FogMode::Exponential => 1,
+            FogMode::Height => 2,
+        };
+        gl::Uniform1i(shader.get_uniform_location("fog_enabled"), self.enabled as i32);
+        gl::Uniform1i(shader.get_uniform_location("fog_mode"), mode);
+        gl::Uniform3fv(shader.get_uniform_location("fog_color"), 1, self.color.as_ptr());
+        gl::Uniform1f(shader.get_uniform_location("fog_density"), self.density);
+        gl::Uniform1f(shader.get_uniform_location("fog_start"), self.start);
+        gl::Uniform1f(shader.get_uniform_location("fog_end"), self.end);
+        gl::Uniform1f(shader.get_uniform_location("fog_base_height"), self.base_height);
+        gl::Uniform1f(shader.get_uniform_location("fog_height_falloff"), self.height_falloff);
+        gl::Uniform1i(shader.get_uniform_location("fog_use_environment"), (self.use_environment && has_environment) as i32);
+        gl::Uniform3fv(shader.get_uniform_location("camera_position"), 1, camera_position.as_ptr());
+    }
+}
@@ -0,0 +1,163 @@
+use std::ptr;
+
+// An offscreen render target with any number of color textures and an optional depth texture.
+// Every attachment is a texture, so later passes can sample what was drawn into it.
+pub struct Framebuffer {
+    pub fbo: u32,
+    pub color_textures: Vec<u32>,
+    pub depth_texture: u32, // 0 when the framebuffer has no depth
+    pub width: i32,
+    pub height: i32,
+    pub samples: i32, // 0 for ordinary textures, otherwise the textures are multisampled
+}
+
+impl Framebuffer {
+    // color_formats are sized internal formats like gl::RGBA16F, one texture is made for each.
+    pub unsafe fn new(width: i32, height: i32, color_formats: &[u32], with_depth: bool) -> Framebuffer {
+        Framebuffer::new_multisampled(width, height, color_formats, with_depth, 0)
+    }
+
+    // Like new(), but every attachment gets the given number of samples per pixel.
+    // Multisampled textures can't be sampled like ordinary ones, so resolve the framebuffer before using it in a later pass.
+    pub unsafe fn new_multisampled(width: i32, height: i32, color_formats: &[u32], with_depth: bool, samples: i32) -> Framebuffer {
+        let target = if samples > 0 { gl::TEXTURE_2D_MULTISAMPLE } else { gl::TEXTURE_2D };
+
+        let mut fbo: u32 = 0;
+        gl::GenFramebuffers(1, &mut fbo);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+
+        let mut color_textures = Vec::with_capacity(color_formats.len());
+        let mut draw_buffers = Vec::with_capacity(color_formats.len());
+        for (i, &internal_format) in color_formats.iter().enumerate() {
+            let texture = create_texture(width, height, internal_format, samples);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0 + i as u32, target, texture, 0);
+            color_textures.push(texture);
+            draw_buffers.push(gl::COLOR_ATTACHMENT0 + i as u32);
+        }
+
+        if draw_buffers.is_empty() {
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+        } else {
+            gl::DrawBuffers(draw_buffers.len() as i32, draw_buffers.as_ptr());
+        }
+
+        let mut depth_texture: u32 = 0;
+        if with_depth {
+            depth_texture = create_texture(width, height, gl::DEPTH_COMPONENT24, samples);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, target, depth_texture, 0);
+        }
+
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Framebuffer with formats {:?} is incomplete.", color_formats);
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        Framebuffer {
+            fbo,
+            color_textures,
+            depth_texture,
+            width,
+            height,
+            samples,
+        }
+    }
+
+    // Averages the samples of every attachment into the matching attachment of an ordinary framebuffer of the same size.
+    pub unsafe fn resolve_into(&self, target: &Framebuffer) {
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.fbo);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, target.fbo);
+
+        // blits only copy from one read buffer to the draw buffers, so the colors go one at a time
+        for i in 0..self.color_textures.len().min(target.color_textures.len()) {
+            let attachment = gl::COLOR_ATTACHMENT0 + i as u32;
+            gl::ReadBuffer(attachment);
+            gl::DrawBuffers(1, &attachment);
+            gl::BlitFramebuffer(0, 0, self.width, self.height, 0, 0, target.width, target.height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+        }
+        if self.depth_texture != 0 && target.depth_texture != 0 {
+            gl::BlitFramebuffer(0, 0, self.width, self.height, 0, 0, target.width, target.height, gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+        }
+
+        // put the draw buffers of the target back the way they were
+        let draw_buffers: Vec<u32> = (0..target.color_textures.len() as u32).map(|i| gl::COLOR_ATTACHMENT0 + i).collect();
+        if !draw_buffers.is_empty() {
+            gl::DrawBuffers(draw_buffers.len() as i32, draw_buffers.as_ptr());
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
+
+    // Copies the depth into another framebuffer of the same size, and leaves that one bound,
+    // so it can go on drawing against what was drawn here.
+    pub unsafe fn copy_depth_into(&self, target: &Framebuffer) {
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.fbo);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, target.fbo);
+        gl::BlitFramebuffer(0, 0, self.width, self.height, 0, 0, target.width, target.height, gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+        target.bind();
+    }
+
+    // Makes the framebuffer the target of the next draws, covering all of it.
+    pub unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        gl::Viewport(0, 0, self.width, self.height);
+    }
+
+    // Binds one of the color textures to a texture unit, so a shader can sample it.
+    pub unsafe fn bind_color(&self, index: usize, texture_unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + texture_unit);
+        gl::BindTexture(gl::TEXTURE_2D, self.color_textures[index]);
+    }
+
+    // Binds the depth texture to a texture unit, so a shader can sample it.
+    pub unsafe fn bind_depth(&self, texture_unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + texture_unit);
+        gl::BindTexture(gl::TEXTURE_2D, self.depth_texture);
+    }
+}
+
+// Goes back to drawing into the window.
+pub unsafe fn bind_default(width: i32, height: i32) {
+    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    gl::Viewport(0, 0, width, height);
+}
+
+// The pixel format and type glTexImage2D wants along with a sized internal format.
+fn pixel_format(internal_format: u32) -> (u32, u32) {
+    match internal_format {
+        gl::RGBA16F | gl::RGBA32F => (gl::RGBA, gl::FLOAT),
+        gl::RGB16F | gl::RGB32F | gl::R11F_G11F_B10F => (gl::RGB, gl::FLOAT),
+        gl::RG16F | gl::RG32F => (gl::RG, gl::FLOAT),
+        gl::R16F | gl::R32F => (gl::RED, gl::FLOAT),
+        gl::RGBA8 | gl::SRGB8_ALPHA8 => (gl::RGBA, gl::UNSIGNED_BYTE),
+        gl::RGB8 | gl::SRGB8 => (gl::RGB, gl::UNSIGNED_BYTE),
+        gl::R8 => (gl::RED, gl::UNSIGNED_BYTE),
+        gl::R32UI => (gl::RED_INTEGER, gl::UNSIGNED_INT),
+        gl::DEPTH_COMPONENT24 | gl::DEPTH_COMPONENT32F => (gl::DEPTH_COMPONENT, gl::FLOAT),
+        _ => panic!("Unsupported framebuffer format {:#x}", internal_format),
+    }
+}
+
+unsafe fn create_texture(width: i32, height: i32, internal_format: u32, samples: i32) -> u32 {
+    let (format, data_type) = pixel_format(internal_format);
+
+    let mut texture: u32 = 0;
+    gl::GenTextures(1, &mut texture);
+
+    // multisampled textures have no filtering or wrapping to set up
+    if samples > 0 {
+        gl::BindTexture(gl::TEXTURE_2D_MULTISAMPLE, texture);
+        gl::TexImage2DMultisample(gl::TEXTURE_2D_MULTISAMPLE, samples, internal_format, width, height, gl::TRUE);
+        return texture;
+    }
+
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as i32, width, height, 0, format, data_type, ptr::null());
+
+    // integer textures can't be filtered
+    let filter = if format == gl::RED_INTEGER { gl::NEAREST } else { gl::LINEAR };
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    texture
+}
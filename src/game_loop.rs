@@ -0,0 +1,249 @@
+extern crate nalgebra_glm as glm;
+use crate::scene_graph;
+
+// Steps the simulation at a fixed rate, however fast the frames come. The time since the last frame goes into an
+// accumulator, and as many whole steps as fit are taken out of it. What is left over says how far the frame is
+// between the last two steps, so the transforms can be blended for rendering.
+pub struct GameLoop {
+    pub timestep: f32,
+    pub max_steps: u32,      // steps taken at most in one frame, so a slow frame doesn't snowball into slower ones
+    pub deterministic: bool, // exactly one step per frame whatever the time, so the same inputs give the same states
+    pub steps: u64,          // taken since the start
+    accumulator: f32,
+    steps_this_frame: u32,
+}
+
+impl GameLoop {
+    pub fn new(rate: f32, max_steps: u32, deterministic: bool) -> GameLoop {
+        GameLoop {
+            timestep: 1.0 / rate,
+            max_steps,
+            deterministic,
+            steps: 0,
+            accumulator: 0.0,
+            steps_this_frame: 0,
+        }
+    }
+
+    // Call once per frame with the time to simulate since the last one, then step while next_step() says so.
+    pub fn begin_frame(&mut self, delta_time: f32) {
+        self.steps_this_frame = 0;
+        if self.deterministic {
+            // no time at all is a paused clock, which holds still here too
+            self.accumulator = if delta_time > 0.0 { self.timestep } else { 0.0 };
+        } else {
+            self.accumulator += delta_time;
+        }
+    }
+
+    pub fn next_step(&mut self) -> bool {
+        if self.steps_this_frame >= self.max_steps {
+            // whatever could not be caught up on is dropped, the game slows down instead
+            self.accumulator = self.accumulator.min(self.timestep);
+            return false;
+        }
+        if self.accumulator < self.timestep {
+            return false;
+        }
+        self.accumulator -= self.timestep;
+        self.steps_this_frame += 1;
+        self.steps += 1;
+        true
+    }
+
+    // How far the frame is from the state before the last step to the state after it.
+    pub fn alpha(&self) -> f32 {
+        if self.deterministic {
+            1.0
+        } else {
+            (self.accumulator / self.timestep).clamp(0.0, 1.0)
+        }
+    }
+}
+
+// The position, rotation and scale of every node in a scene graph at one moment.
+pub struct TransformState {
+    nodes: Vec<(*mut scene_graph::SceneNode, glm::Vec3, glm::Vec3, glm::Vec3)>,
+}
+
+impl TransformState {
+    // The root is borrowed mutably, restore() and interpolate() change the nodes through the pointers kept here.
+    pub unsafe fn capture(root: &mut scene_graph::SceneNode) -> TransformState {
+        let mut nodes = vec![];
+        let mut stack = vec![root as *mut scene_graph::SceneNode];
+        while let Some(node) = stack.pop() {
+            nodes.push((node, (*node).position, (*node).rotation, (*node).scale));
+            stack.extend((*node).children.iter().cloned());
+        }
+        TransformState { nodes }
+    }
+
+    // Puts the nodes back the way they were captured.
+    pub unsafe fn restore(&self) {
+        for &(node, position, rotation, scale) in &self.nodes {
+            (*node).position = position;
+            (*node).rotation = rotation;
+            (*node).scale = scale;
+        }
+    }
+
+    // Places the nodes between two states of the same scene graph, alpha 0 being previous and 1 being current.
+    pub unsafe fn interpolate(previous: &TransformState, current: &TransformState, alpha: f32) {
+        for (before, after) in previous.nodes.iter().zip(current.nodes.iter()) {
+            // a node added between the two has nothing to blend from
+            if before.0 != after.0 {
+                continue;
+            }
+            let node = after.0;
+            (*node).position = glm::lerp(&before.1, &after.1, alpha);
+            (*node).rotation = before.2 + wrap_angles(&(after.2 - before.2)) * alpha;
+            (*node).scale = glm::lerp(&before.3, &after.3, alpha);
+        }
+    }
+}
+
+// The turn the shortest way around, so an angle wrapping from pi to -pi doesn't spin the node all the way back.
+pub fn wrap_angles(angles: &glm::Vec3) -> glm::Vec3 {
+    let tau = std::f32::consts::PI * 2.0;
+    angles.map(|angle| angle - tau * (angle / tau).round())
+}
+
+// A hash of the position, rotation and scale of every node, bit for bit, to check two runs ended up the same.
+pub unsafe fn state_hash(root: &scene_graph::SceneNode) -> u64 {
+    // FNV-1a
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+        for value in node.position.iter().chain(node.rotation.iter()).chain(node.scale.iter()) {
+            for byte in value.to_bits().to_le_bytes().iter() {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        stack.extend(node.children.iter().map(|&child| &*child));
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics;
+    use crate::util::Random;
+
+    // Flies a helicopter through the game loop with frames of the given lengths, steering from a seeded
+    // random input every step, and returns the hash of the scene after every frame and the steps taken.
+    fn run(frame_times: &[f32], seed: u32, deterministic: bool) -> (Vec<u64>, u64) {
+        unsafe {
+            let mut root = scene_graph::SceneNode::new();
+            let mut body = scene_graph::SceneNode::new();
+            let rotor = scene_graph::SceneNode::new();
+            body.add_child(&rotor);
+            root.add_child(&body);
+
+            // a power of two, so frame times in steps and half steps add up without rounding
+            let mut game_loop = GameLoop::new(128.0, 8, deterministic);
+            let mut helicopter = physics::Helicopter::new(glm::vec3(0.0, 9.0, 0.0));
+            let mut random = Random::new(seed);
+            let mut hashes = vec![];
+            for &frame_time in frame_times {
+                game_loop.begin_frame(frame_time);
+                while game_loop.next_step() {
+                    let controls = physics::Controls {
+                        collective: random.float(),
+                        cyclic: glm::vec2(random.float() * 2.0 - 1.0, random.float() * 2.0 - 1.0),
+                        pedal: random.float() * 2.0 - 1.0,
+                    };
+                    helicopter.step(game_loop.timestep, &controls);
+                    helicopter.apply(&mut body);
+                    let rotor = &mut *body.children[0];
+                    rotor.rotation.y += 10.0 * game_loop.timestep;
+                }
+                hashes.push(state_hash(&root));
+            }
+            (hashes, game_loop.steps)
+        }
+    }
+
+    // frame times all over the place, like a real run would have
+    fn frame_times(seed: u32, count: usize) -> Vec<f32> {
+        let mut random = Random::new(seed);
+        (0..count).map(|_| random.float() * 0.05).collect()
+    }
+
+    #[test]
+    fn deterministic_runs_hash_the_same() {
+        let first = run(&frame_times(1, 200), 7, true);
+        // the frame times don't matter in deterministic mode, only the inputs
+        let second = run(&frame_times(2, 200), 7, true);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn different_inputs_hash_differently() {
+        let (first, _) = run(&frame_times(1, 200), 7, true);
+        let (second, _) = run(&frame_times(1, 200), 8, true);
+        assert_ne!(first.last(), second.last());
+    }
+
+    #[test]
+    fn split_frames_take_the_same_steps() {
+        // frames of up to four steps in half steps, so most of them leave time over for the next one
+        let mut random = Random::new(3);
+        let halves: Vec<u32> = (0..100).map(|_| 1 + (random.float() * 8.0) as u32).collect();
+        let whole: Vec<f32> = halves.iter().map(|&count| count as f32 / 256.0).collect();
+        let split: Vec<f32> = halves.iter().flat_map(|&count| vec![1.0 / 256.0; count as usize]).collect();
+
+        let (whole_hashes, whole_steps) = run(&whole, 7, false);
+        let (split_hashes, split_steps) = run(&split, 7, false);
+        assert_eq!(whole_steps, halves.iter().sum::<u32>() as u64 / 2);
+        assert_eq!(whole_steps, split_steps);
+        assert_eq!(whole_hashes.last(), split_hashes.last());
+    }
+
+    #[test]
+    fn slow_frames_are_capped() {
+        let mut game_loop = GameLoop::new(120.0, 8, false);
+        game_loop.begin_frame(1.0);
+        let mut steps = 0;
+        while game_loop.next_step() {
+            steps += 1;
+        }
+        assert_eq!(steps, 8);
+        assert_eq!(game_loop.steps, 8);
+        // what couldn't be caught up on is dropped, at most one step is left over for the next frame
+        assert!(game_loop.alpha() <= 1.0);
+        game_loop.begin_frame(0.0);
+        let mut steps = 0;
+        while game_loop.next_step() {
+            steps += 1;
+        }
+        assert!(steps <= 1);
+    }
+
+    #[test]
+    fn alpha_is_the_time_left_over() {
+        let mut game_loop = GameLoop::new(100.0, 8, false);
+        game_loop.begin_frame(0.025);
+        let mut steps = 0;
+        while game_loop.next_step() {
+            steps += 1;
+        }
+        assert_eq!(steps, 2);
+        assert!((game_loop.alpha() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn deterministic_takes_one_step_and_none_when_paused() {
+        let mut game_loop = GameLoop::new(120.0, 8, true);
+        for (delta_time, expected) in [(0.5, 1), (0.0001, 1), (0.0, 0)] {
+            game_loop.begin_frame(delta_time);
+            let mut steps = 0;
+            while game_loop.next_step() {
+                steps += 1;
+            }
+            assert_eq!(steps, expected);
+        }
+        assert_eq!(game_loop.alpha(), 1.0);
+    }
+}
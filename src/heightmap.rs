@@ -0,0 +1,267 @@
+extern crate nalgebra_glm as glm;
+use crate::mesh;
+use crate::util::Random;
+
+// A grid of heights between 0 and 1, read from a grayscale image or generated.
+pub struct HeightField {
+    pub width: usize,
+    pub depth: usize,
+    pub heights: Vec<f32>, // row by row along z, width values per row
+}
+
+impl HeightField {
+    pub fn from_image(image: &image::GrayImage) -> HeightField {
+        HeightField {
+            width: image.width() as usize,
+            depth: image.height() as usize,
+            heights: image.pixels().map(|pixel| pixel[0] as f32 / 255.0).collect(),
+        }
+    }
+
+    // The height at a grid point, clamped to the edges.
+    pub fn get(&self, x: i64, z: i64) -> f32 {
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let z = z.clamp(0, self.depth as i64 - 1) as usize;
+        self.heights[z * self.width + x]
+    }
+
+    // Writes the heights as a grayscale png, so a generated map can be kept and tweaked in an image editor.
+    pub fn save(&self, path: &str) -> image::ImageResult<()> {
+        let pixels = self.heights.iter().map(|h| (h.clamp(0.0, 1.0) * 255.0).round() as u8).collect();
+        let image = image::GrayImage::from_raw(self.width as u32, self.depth as u32, pixels).expect("The heights don't fill the image");
+        image.save(path)
+    }
+
+    // A grid mesh centered on the origin, with scale world units between the samples and height world units
+    // between black and white. The normals come from the slope to the neighbouring samples.
+    // With uvs, the texture is stretched once over the whole grid.
+    pub fn to_mesh(&self, scale: f32, height: f32, uvs: bool) -> mesh::Mesh {
+        if self.width < 2 || self.depth < 2 {
+            panic!("A heightmap needs at least 2x2 samples, got {}x{}", self.width, self.depth);
+        }
+
+        let offset_x = (self.width - 1) as f32 * scale * 0.5;
+        let offset_z = (self.depth - 1) as f32 * scale * 0.5;
+
+        let mut vertices = Vec::with_capacity(self.width * self.depth * 3);
+        let mut normals = Vec::with_capacity(self.width * self.depth * 3);
+        let mut texture_coordinates = vec![];
+        for z in 0..self.depth {
+            for x in 0..self.width {
+                let (xi, zi) = (x as i64, z as i64);
+                vertices.extend_from_slice(&[x as f32 * scale - offset_x, self.get(xi, zi) * height, z as f32 * scale - offset_z]);
+
+                // central differences, one sided at the edges since get() clamps
+                let slope_x = (self.get(xi + 1, zi) - self.get(xi - 1, zi)) * height / (2.0 * scale);
+                let slope_z = (self.get(xi, zi + 1) - self.get(xi, zi - 1)) * height / (2.0 * scale);
+                let normal = glm::normalize(&glm::vec3(-slope_x, 1.0, -slope_z));
+                normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+
+                if uvs {
+                    texture_coordinates.extend_from_slice(&[x as f32 / (self.width - 1) as f32, z as f32 / (self.depth - 1) as f32]);
+                }
+            }
+        }
+
+        // two triangles per square, wound counter-clockwise seen from above
+        let mut indices = Vec::with_capacity((self.width - 1) * (self.depth - 1) * 6);
+        for z in 0..self.depth - 1 {
+            for x in 0..self.width - 1 {
+                let corner = (z * self.width + x) as u32;
+                let (right, below) = (corner + 1, corner + self.width as u32);
+                indices.extend_from_slice(&[corner, below, right, right, below, below + 1]);
+            }
+        }
+
+        let colors = vec![1.0; self.width * self.depth * 4];
+        mesh::Mesh::new(vertices, normals, colors, indices).with_uvs(texture_coordinates)
+    }
+}
+
+// Makes moon-like height fields: rolling hills from fractal Perlin noise, with craters stamped on top.
+// The same seed and settings always give the same map.
+pub struct Generator {
+    pub seed: u32,
+    pub octaves: u32,
+    pub frequency: f32,   // noise periods across the whole map for the first octave
+    pub lacunarity: f32,  // how much the frequency grows each octave
+    pub persistence: f32, // how much the amplitude shrinks each octave
+    pub craters: u32,
+    pub crater_radius: (f32, f32), // smallest and largest radius, as a fraction of the map width
+    pub crater_depth: f32,         // depth of a crater relative to its radius
+    pub crater_rim: f32,           // height of the rim relative to the depth
+}
+
+impl Generator {
+    pub fn new(seed: u32) -> Generator {
+        Generator {
+            seed,
+            octaves: 6,
+            frequency: 4.0,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            craters: 40,
+            crater_radius: (0.01, 0.08),
+            crater_depth: 0.5,
+            crater_rim: 0.2,
+        }
+    }
+
+    pub fn generate(&self, width: usize, depth: usize) -> HeightField {
+        let mut random = Random::new(self.seed);
+        let noise = Perlin::new(&mut random);
+
+        let mut heights = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                let point = glm::vec2(x as f32 / width as f32, z as f32 / width as f32) * self.frequency;
+                heights.push(self.fbm(&noise, point) * 0.5);
+            }
+        }
+
+        // the big craters go first, so the small ones land on top of them like on the real thing
+        let mut craters: Vec<(glm::Vec2, f32)> = (0..self.craters).map(|_| {
+            let center = glm::vec2(random.float() * width as f32, random.float() * depth as f32);
+            // small craters are a lot more common than big ones
+            let t = random.float() * random.float();
+            let radius = (self.crater_radius.0 + (self.crater_radius.1 - self.crater_radius.0) * t) * width as f32;
+            (center, radius)
+        }).collect();
+        craters.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        for (center, radius) in craters {
+            self.stamp_crater(&mut heights, width, depth, center, radius);
+        }
+
+        // stretched to use the whole range, so the height of the mesh is the height of the tallest peak
+        let low = heights.iter().cloned().fold(f32::MAX, f32::min);
+        let high = heights.iter().cloned().fold(f32::MIN, f32::max);
+        let range = (high - low).max(1e-6);
+        for h in heights.iter_mut() {
+            *h = (*h - low) / range;
+        }
+
+        HeightField { width, depth, heights }
+    }
+
+    // Fractal Brownian motion, the octaves of noise added together, roughly in [-1, 1].
+    fn fbm(&self, noise: &Perlin, point: glm::Vec2) -> f32 {
+        let (mut sum, mut amplitude, mut total, mut frequency) = (0.0, 1.0, 0.0, 1.0);
+        for _ in 0..self.octaves {
+            sum += noise.sample(point * frequency) * amplitude;
+            total += amplitude;
+            amplitude *= self.persistence;
+            frequency *= self.lacunarity;
+        }
+        sum / total.max(1e-6)
+    }
+
+    // A bowl dug into the ground with a raised rim around it, fading out over half a radius.
+    fn stamp_crater(&self, heights: &mut [f32], width: usize, depth: usize, center: glm::Vec2, radius: f32) {
+        // heights are relative to the map width, like the radius
+        let crater_depth = self.crater_depth * radius / width as f32;
+        let rim_width = 0.5;
+        let reach = radius * (1.0 + rim_width);
+
+        let min_x = (center.x - reach).floor().max(0.0) as usize;
+        let max_x = ((center.x + reach).ceil() as usize).min(width - 1);
+        let min_z = (center.y - reach).floor().max(0.0) as usize;
+        let max_z = ((center.y + reach).ceil() as usize).min(depth - 1);
+        for z in min_z..=max_z {
+            for x in min_x..=max_x {
+                let d = glm::distance(&glm::vec2(x as f32, z as f32), &center) / radius;
+                let shape = if d < 1.0 {
+                    d * d - 1.0 + self.crater_rim
+                } else if d < 1.0 + rim_width {
+                    let t = 1.0 - (d - 1.0) / rim_width;
+                    self.crater_rim * t * t
+                } else {
+                    0.0
+                };
+                heights[z * width + x] += shape * crater_depth;
+            }
+        }
+    }
+}
+
+// Classic 2D gradient noise, with the permutation shuffled by the seed.
+struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    fn new(random: &mut Random) -> Perlin {
+        let mut table: Vec<u8> = (0..=255).collect();
+        for i in (1..table.len()).rev() {
+            let j = ((random.float() * (i + 1) as f32) as usize).min(i);
+            table.swap(i, j);
+        }
+        let mut permutation = [0; 512];
+        for (i, value) in permutation.iter_mut().enumerate() {
+            *value = table[i % 256];
+        }
+        Perlin { permutation }
+    }
+
+    fn gradient(&self, x: i32, y: i32, offset: glm::Vec2) -> f32 {
+        let hash = self.permutation[self.permutation[(x & 255) as usize] as usize + (y & 255) as usize];
+        // eight directions around the circle
+        let angle = (hash & 7) as f32 * std::f32::consts::FRAC_PI_4;
+        angle.cos() * offset.x + angle.sin() * offset.y
+    }
+
+    // Roughly in [-1, 1].
+    fn sample(&self, point: glm::Vec2) -> f32 {
+        let cell = glm::floor(&point);
+        let (x, y) = (cell.x as i32, cell.y as i32);
+        let f = point - cell;
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let (u, v) = (fade(f.x), fade(f.y));
+
+        let a = self.gradient(x, y, f);
+        let b = self.gradient(x + 1, y, f - glm::vec2(1.0, 0.0));
+        let c = self.gradient(x, y + 1, f - glm::vec2(0.0, 1.0));
+        let d = self.gradient(x + 1, y + 1, f - glm::vec2(1.0, 1.0));
+        let bottom = a + (b - a) * u;
+        let top = c + (d - c) * u;
+        (bottom + (top - bottom) * v) * std::f32::consts::SQRT_2
+    }
+}
+
+// Where the terrain comes from, picked with --terrain=<file.obj|file.png|random[:seed]>.
+#[derive(Clone, PartialEq, Debug)]
+pub enum TerrainSource {
+    Obj(String),
+    Heightmap(String),
+    Procedural(u32),
+}
+
+impl TerrainSource {
+    pub fn parse(value: &str) -> Result<TerrainSource, String> {
+        if value == "random" {
+            return Ok(TerrainSource::Procedural(0x6d6f6f6e));
+        }
+        if let Some(seed) = value.strip_prefix("random:") {
+            return seed.parse().map(TerrainSource::Procedural).map_err(|_| format!("Invalid terrain seed \"{}\"", seed));
+        }
+        let lower = value.to_lowercase();
+        if lower.ends_with(".obj") {
+            Ok(TerrainSource::Obj(value.to_string()))
+        } else if lower.ends_with(".png") {
+            Ok(TerrainSource::Heightmap(value.to_string()))
+        } else {
+            Err(format!("Unknown terrain \"{}\", expected an .obj, a .png or random[:seed]", value))
+        }
+    }
+
+    pub fn from_args(default: &str) -> TerrainSource {
+        for arg in std::env::args().skip(1) {
+            if let Some(value) = arg.strip_prefix("--terrain=") {
+                match TerrainSource::parse(value) {
+                    Ok(source) => return source,
+                    Err(e) => println!("{}, falling back to {}.", e, default),
+                }
+            }
+        }
+        TerrainSource::Obj(default.to_string())
+    }
+}
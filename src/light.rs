@@ -0,0 +1,177 @@
+extern crate nalgebra_glm as glm;
+use crate::scene_graph;
+use crate::shader;
+
+// Has to match the size of the lights array in lighting.frag
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot,
+}
+
+// A light that lives on a scene node. Position and direction are given in the local space of the node,
+// so the light follows the node around when it is animated.
+#[derive(Clone, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: glm::Vec3,
+    pub intensity: f32,
+    pub position: glm::Vec3,
+    pub direction: glm::Vec3,
+    pub range: f32,
+    pub inner_angle: f32, // radians, full intensity inside this cone (spot only)
+    pub outer_angle: f32, // radians, no light outside this cone (spot only)
+    pub blink_rate: f32,  // blinks per second, 0.0 means the light is always on
+    pub cast_shadows: bool, // only honoured for directional lights
+    pub enabled: bool,
+}
+
+// A light that has been moved into world space, ready to be sent to the shader.
+#[derive(Clone, Copy, Debug)]
+pub struct WorldLight {
+    pub kind: LightKind,
+    pub color: glm::Vec3,
+    pub position: glm::Vec3,
+    pub direction: glm::Vec3,
+    pub range: f32,
+    pub cos_inner: f32,
+    pub cos_outer: f32,
+    pub cast_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(direction: glm::Vec3, color: glm::Vec3, intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Directional,
+            color,
+            intensity,
+            position: glm::zero(),
+            direction: glm::normalize(&direction),
+            range: 0.0,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
+            blink_rate: 0.0,
+            cast_shadows: false,
+            enabled: true,
+        }
+    }
+
+    pub fn point(position: glm::Vec3, color: glm::Vec3, intensity: f32, range: f32) -> Light {
+        Light {
+            kind: LightKind::Point,
+            color,
+            intensity,
+            position,
+            direction: glm::vec3(0.0, -1.0, 0.0),
+            range,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
+            blink_rate: 0.0,
+            cast_shadows: false,
+            enabled: true,
+        }
+    }
+
+    pub fn spot(position: glm::Vec3, direction: glm::Vec3, color: glm::Vec3, intensity: f32, range: f32, inner_angle: f32, outer_angle: f32) -> Light {
+        Light {
+            kind: LightKind::Spot,
+            color,
+            intensity,
+            position,
+            direction: glm::normalize(&direction),
+            range,
+            inner_angle,
+            outer_angle,
+            blink_rate: 0.0,
+            cast_shadows: false,
+            enabled: true,
+        }
+    }
+
+    // Turns the light into a beacon that blinks on and off `rate` times per second.
+    pub fn blinking(mut self, rate: f32) -> Light {
+        self.blink_rate = rate;
+        self
+    }
+
+    // Lets the light cast shadows through the shadow map.
+    pub fn casting_shadows(mut self) -> Light {
+        self.cast_shadows = true;
+        self
+    }
+
+    // A blinking light is lit for the first half of every period.
+    pub fn is_lit(&self, total_time: f32) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.blink_rate <= 0.0 {
+            return true;
+        }
+        (total_time * self.blink_rate).fract() < 0.5
+    }
+
+    // Moves the light into world space using the transformation matrix of the node it is attached to.
+    pub fn to_world(&self, transformation_matrix: &glm::Mat4) -> WorldLight {
+        let position = transformation_matrix * glm::vec4(self.position.x, self.position.y, self.position.z, 1.0);
+        let direction = glm::mat4_to_mat3(transformation_matrix) * self.direction;
+
+        WorldLight {
+            kind: self.kind,
+            color: self.color * self.intensity,
+            position: glm::vec3(position.x, position.y, position.z),
+            direction: glm::normalize(&direction),
+            range: self.range,
+            cos_inner: self.inner_angle.cos(),
+            cos_outer: self.outer_angle.cos(),
+            cast_shadows: self.cast_shadows,
+        }
+    }
+}
+
+// Walks the scene graph and collects every lit light in world space.
+// Has to be called after the node transformations have been updated for the frame.
+pub unsafe fn collect_lights(root: &scene_graph::SceneNode, total_time: f32, lights: &mut Vec<WorldLight>) {
+    for light in &root.lights {
+        if light.is_lit(total_time) {
+            lights.push(light.to_world(&root.current_transformation_matrix));
+        }
+    }
+
+    // Recurse
+    for &child in &root.children {
+        collect_lights(&*child, total_time, lights);
+    }
+}
+
+// The direction of the first directional light that casts shadows, which is the one the shadow map is drawn from.
+pub fn shadow_caster_direction(lights: &[WorldLight]) -> Option<glm::Vec3> {
+    lights.iter()
+        .find(|light| light.kind == LightKind::Directional && light.cast_shadows)
+        .map(|light| light.direction)
+}
+
+// Sends the lights to the lights[] uniform array of the active shader. Lights beyond MAX_LIGHTS are dropped.
+pub unsafe fn upload_lights(shader: &shader::Shader, lights: &[WorldLight]) {
+    let count = lights.len().min(MAX_LIGHTS);
+    gl::Uniform1i(shader.get_uniform_location("light_count"), count as i32);
+
+    for (i, light) in lights.iter().take(count).enumerate() {
+        let kind = match light.kind {
+            LightKind::Directional => 0,
+            LightKind::Point => 1,
+            LightKind::Spot => 2,
+        };
+        gl::Uniform1i(shader.get_uniform_location(&format!("lights[{}].kind", i)), kind);
+        gl::Uniform3fv(shader.get_uniform_location(&format!("lights[{}].color", i)), 1, light.color.as_ptr());
+        gl::Uniform3fv(shader.get_uniform_location(&format!("lights[{}].position", i)), 1, light.position.as_ptr());
+        gl::Uniform3fv(shader.get_uniform_location(&format!("lights[{}].direction", i)), 1, light.direction.as_ptr());
+        gl::Uniform1f(shader.get_uniform_location(&format!("lights[{}].range", i)), light.range);
+        gl::Uniform1f(shader.get_uniform_location(&format!("lights[{}].cos_inner", i)), light.cos_inner);
+        gl::Uniform1f(shader.get_uniform_location(&format!("lights[{}].cos_outer", i)), light.cos_outer);
+        gl::Uniform1i(shader.get_uniform_location(&format!("lights[{}].cast_shadows", i)), light.cast_shadows as i32);
+    }
+}
@@ -8,12 +8,79 @@ mod shader;
 mod util;
 mod camera;
 
-use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
+use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, MouseButton, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
 use glutin::event_loop::ControlFlow;
 
 const SCREEN_W: u32 = 600;
 const SCREEN_H: u32 = 500;
 
+// shadow map settings
+const SHADOW_RESOLUTION: i32 = 2048;
+const SHADOW_CASCADES: usize = 4;
+const SHADOW_BIAS: f32 = 0.002;
+const SHADOW_PCF_RADIUS: i32 = 1;
+const SHADOW_DISTANCE: f32 = 1000.0;
+const SHADOW_SPLIT_LAMBDA: f32 = 0.75;
+const SHADOW_CASCADE_BLEND: f32 = 0.1;
+
+// in sRGB, it is turned linear before clearing the hdr framebuffer
+const CLEAR_COLOR: [f32; 3] = [0.163, 0.163, 0.163];
+
+const TONE_MAPPING: postprocess::ToneMapping = postprocess::ToneMapping::Aces;
+const EXPOSURE: f32 = 1.0;
+
+// K switches between casting rays through the bvh of every node and reading the node from an id buffer
+const PICKING_MODE: picking::PickingMode = picking::PickingMode::Ray;
+
+// I switches between sorting the transparent nodes and weighted blended order-independent transparency
+const TRANSPARENCY_MODE: transparency::TransparencyMode = transparency::TransparencyMode::Sorted;
+
+// --glass stands a row of tinted panes next to where the helicopter starts, to see the transparency modes on.
+// The colors are in sRGB like the other vertex colors, the position in the space of the terrain.
+const GLASS_COLORS: [[f32; 4]; 3] = [[0.8, 0.2, 0.2, 0.4], [0.2, 0.8, 0.2, 0.4], [0.2, 0.3, 0.9, 0.4]];
+const GLASS_POSITION: [f32; 3] = [14.0, 9.0, 0.0];
+
+// the passes F1 to F5 turn on and off, in the order they run. The tone mapping always runs between bloom and fxaa,
+// it is what takes the image from linear hdr colors to sRGB for the window.
+const POST_PASSES: [&str; 5] = ["bloom", "fxaa", "color_grading", "chromatic_aberration", "vignette"];
+
+// in linear colors, scaled down by the ambient occlusion
+const AMBIENT_COLOR: [f32; 3] = [0.06, 0.06, 0.07];
+
+// screen-space ambient occlusion settings
+const SSAO_KERNEL_SIZE: usize = 32;
+const SSAO_RADIUS: f32 = 3.0;
+const SSAO_INTENSITY: f32 = 1.0;
+
+// texture units used by the main shader, the deferred lighting passes put the G-buffer on 2 to 5
+const SHADOW_TEXTURE_UNIT: u32 = 0;
+const ENVIRONMENT_TEXTURE_UNIT: u32 = 1;
+const SSAO_TEXTURE_UNIT: u32 = 6;
+
+// the terrain is split 4^depth ways, and a chunk is split further when its error would cover more pixels than this
+const TERRAIN_LOD_DEPTH: u32 = 3;
+const TERRAIN_LOD_RESOLUTION: u32 = 16;
+const TERRAIN_LOD_MAX_ERROR: f32 = 4.0;
+
+// for terrains from a heightmap or --terrain=random, in world units between the samples and between black and white
+const HEIGHTMAP_SCALE: f32 = 2.0;
+const HEIGHTMAP_HEIGHT: f32 = 40.0;
+const PROCEDURAL_TERRAIN_SIZE: usize = 257;
+
+// the collective moves this much per second while held
+const COLLECTIVE_RATE: f32 = 0.5;
+
+// steps of the simulation per second, and the most taken in one frame before the game slows down instead
+const SIMULATION_RATE: f32 = 120.0;
+const MAX_SIMULATION_STEPS: u32 = 8;
+// keyframes the zombie path is sampled into, once around
+const ZOMBIE_PATH_SAMPLES: usize = 128;
+// how much faster or slower each press of = or - makes the clock
+const TIME_SCALE_FACTOR: f32 = 2.0;
+
+// the gap kept between the bottom of the helicopter and the ground
+const HELICOPTER_GROUND_CLEARANCE: f32 = 0.5;
+
 // --- last assignment import ---
 pub mod mesh;
 pub mod scene_graph;
@@ -21,6 +88,31 @@ pub mod object;
 pub mod VAO;
 pub mod toolbox;
 pub mod animate;
+pub mod light;
+pub mod shadow;
+pub mod skybox;
+pub mod fog;
+pub mod framebuffer;
+pub mod texture;
+pub mod postprocess;
+pub mod antialiasing;
+pub mod deferred;
+pub mod ssao;
+pub mod transparency;
+pub mod render_queue;
+pub mod bounds;
+pub mod terrain_lod;
+pub mod heightmap;
+pub mod terrain_sampler;
+pub mod bvh;
+pub mod picking;
+pub mod collision;
+pub mod physics;
+pub mod game_loop;
+pub mod clock;
+pub mod animation;
+pub mod clip_file;
+pub mod animation_state;
 // -------------------------------
 
 // == // Helper functions to make interacting with OpenGL a little bit prettier. You *WILL* need these! // == //
@@ -48,32 +140,99 @@ fn offset<T>(n: u32) -> *const c_void {
 
 /*Key pressed helper function*/
 
-unsafe fn draw_scene(root: &scene_graph::SceneNode, view_projection_matrix: &glm::Mat4) {
-    // Check if node is drawable, set uniforms, draw.
-    
-    if root.index_count > -1 {
-        // checking if the indexcount is greater than -1, if it is then it contains items that will be drawn. 
-        
-        gl::BindVertexArray(root.vao_id);
-        gl::UniformMatrix4fv(3, 1 as GLsizei, gl::FALSE, (view_projection_matrix * root.current_transformation_matrix).as_ptr()); // sending in the MVP - matrix
-        gl::UniformMatrix4fv(4, 1 as GLsizei, gl::FALSE, (root.current_transformation_matrix).as_ptr()); // sending in the model matrix
-        gl::DrawElements(
-            gl::TRIANGLES, 
-            3 * root.index_count,
-            gl::UNSIGNED_INT, 
-            ptr::null()
-        ); 
+// Sets up the post-processing passes in the order they run. The tone mapping splits the chain in two:
+// the passes before it work on the hdr image, the ones after it on the final sRGB colors.
+unsafe fn build_post_chain(width: i32, height: i32) -> postprocess::PostChain {
+    use postprocess::{Pass, Param};
+
+    let lut = match texture::load_lut("./resources/grading_lut.png") {
+        Ok(lut) => lut,
+        // a missing table is fine, a broken one is worth knowing about
+        Err(image::ImageError::IoError(_)) => texture::identity_lut(16),
+        Err(e) => {
+            println!("{}, grading with an identity table instead.", e);
+            texture::identity_lut(16)
+        }
+    };
+
+    let mut chain = postprocess::PostChain::new(width, height);
+    chain.add(Pass::new("bloom", "./shaders/post_bloom.frag")
+        .with_mipmapped_input()
+        .with_param("threshold", Param::Float(1.0))
+        .with_param("intensity", Param::Float(0.6)));
+    chain.add(Pass::new("tonemap", "./shaders/tonemap.frag")
+        .with_param("operator", Param::Int(TONE_MAPPING.id()))
+        .with_param("exposure", Param::Float(EXPOSURE)));
+    chain.add(Pass::new("fxaa", "./shaders/post_fxaa.frag")
+        .with_param("span_max", Param::Float(8.0)));
+    chain.add(Pass::new("color_grading", "./shaders/post_color_grading.frag")
+        .with_texture("lut", gl::TEXTURE_3D, lut)
+        .with_param("intensity", Param::Float(1.0)));
+    chain.add(Pass::new("chromatic_aberration", "./shaders/post_chromatic_aberration.frag")
+        .with_param("strength", Param::Float(0.004)));
+    chain.add(Pass::new("vignette", "./shaders/post_vignette.frag")
+        .with_param("intensity", Param::Float(0.5))
+        .with_param("radius", Param::Float(0.75))
+        .with_param("softness", Param::Float(0.45)));
+    chain
+}
+
+// Plays the clip in the file on the nodes under the root. A clip that can't be loaded or doesn't fit the nodes
+// is reported, and an empty one plays instead.
+unsafe fn load_clip_player(path: &str, root: &mut scene_graph::SceneNode, mode: animation::PlayMode) -> animation::AnimationPlayer {
+    let name = std::path::Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or(path);
+    let empty = || std::rc::Rc::new(animation::AnimationClip::new(name));
+    let clip = match clip_file::load(path) {
+        Ok(clip) => std::rc::Rc::new(clip),
+        Err(e) => {
+            println!("{}, the clip will do nothing.", e);
+            empty()
+        }
+    };
+    match animation::AnimationPlayer::bind(clip, &mut *root, mode) {
+        Ok(player) => player,
+        Err(e) => {
+            println!("{}, the clip will do nothing.", e);
+            animation::AnimationPlayer::new(empty(), root, mode)
+        }
     }
+}
 
-    // checking if the root has no more children. 
-    if root.children.len() == 0 {
-        return;
+// Looks for six faces in resources/skybox/, and falls back to a single panorama in resources/skybox.png.
+// Without either the scene is drawn on the plain clear color.
+unsafe fn load_skybox() -> Option<skybox::Skybox> {
+    let face_paths: Vec<String> = skybox::FACE_NAMES.iter().map(|name| format!("./resources/skybox/{}.png", name)).collect();
+    let face_paths: Vec<&str> = face_paths.iter().map(|path| path.as_str()).collect();
+
+    match skybox::Skybox::from_faces(&face_paths, texture::ColorSpace::Srgb) {
+        Ok(sky) => return Some(sky),
+        Err(e) => println!("No skybox faces found ({}), trying a panorama instead.", e),
     }
+    match skybox::Skybox::from_equirectangular("./resources/skybox.png", 1024, texture::ColorSpace::Srgb) {
+        Ok(sky) => Some(sky),
+        Err(e) => {
+            println!("No skybox panorama found ({}), using the clear color.", e);
+            None
+        }
+    }
+}
 
-    // Recurse
-    for &child in &root.children {
-        draw_scene(&*child, view_projection_matrix);
+// Sends the lights, shadows, ambient occlusion, sky and fog to a shader that links lighting.frag,
+// the forward shader or one of the deferred lighting passes.
+unsafe fn upload_lighting(shader: &shader::Shader, lights: &[light::WorldLight], shadow_map: &shadow::ShadowMap, ssao: &ssao::Ssao, camera: &camera::Camera, sky: &Option<skybox::Skybox>, fog: &fog::Fog) {
+    shader.activate();
+    light::upload_lights(shader, lights);
+    shadow_map.bind(shader, SHADOW_TEXTURE_UNIT);
+    ssao.bind(shader, SSAO_TEXTURE_UNIT);
+    gl::Uniform3fv(shader.get_uniform_location("ambient_color"), 1, AMBIENT_COLOR.as_ptr());
+    gl::UniformMatrix4fv(shader.get_uniform_location("view_matrix"), 1, gl::FALSE, camera.view_matrix().as_ptr());
+
+    // the environment sampler needs its own unit even without a sky, two sampler types can't share one
+    match sky {
+        Some(sky) => sky.bind(shader, ENVIRONMENT_TEXTURE_UNIT),
+        None => gl::Uniform1i(shader.get_uniform_location("environment_map"), ENVIRONMENT_TEXTURE_UNIT as i32),
     }
+    fog.upload(shader, &camera.position(), sky.is_some());
 }
 
 unsafe fn update_node_transformations(root: &mut scene_graph::SceneNode, transformation_so_far: &glm::Mat4) {
@@ -97,11 +256,15 @@ unsafe fn update_node_transformations(root: &mut scene_graph::SceneNode, transfo
     }
 
 
-    rotate_item = glm::translation(&root.reference_point) * rotate_item * glm::translation(&glm::vec3(-root.reference_point.x, -root.reference_point.y, -root.reference_point.z));
+    // scaled and rotated around the reference point
+    rotate_item = glm::translation(&root.reference_point) * rotate_item * glm::scaling(&root.scale) * glm::translation(&glm::vec3(-root.reference_point.x, -root.reference_point.y, -root.reference_point.z));
     
     
     root.current_transformation_matrix =  transformation_so_far * glm::translation(&root.position) * rotate_item ;
 
+    // the bounds of the mesh follow the node into world space
+    root.world_sphere = root.bounding_sphere.map(|sphere| sphere.transformed(&root.current_transformation_matrix));
+    root.world_bounds = root.bounding_box.map(|aabb| aabb.transformed(&root.current_transformation_matrix));
     
     // checking if the root has no more children. 
     if root.children.len() == 0 {
@@ -111,6 +274,14 @@ unsafe fn update_node_transformations(root: &mut scene_graph::SceneNode, transfo
     for &child in &root.children {// Recurse
         update_node_transformations(&mut *child,
         &root.current_transformation_matrix);
+
+        // the world bounds of a node cover its children too, so a whole helicopter can be culled at once
+        if let Some(child_bounds) = (*child).world_bounds {
+            root.world_bounds = Some(match root.world_bounds {
+                Some(bounds) => bounds.union(&child_bounds),
+                None => child_bounds,
+            });
+        }
     }
 }
 
@@ -122,8 +293,21 @@ fn main() {
         .with_title("Gloom-rs")
         .with_resizable(false)
         .with_inner_size(glutin::dpi::LogicalSize::new(SCREEN_W, SCREEN_H));
+    // picked once at startup with --aa=off, --aa=fxaa or --aa=msaa4
+    let anti_aliasing = antialiasing::AntiAliasing::from_args();
+    println!("Anti-aliasing: {:?}", anti_aliasing);
+    // picked once at startup with --renderer=forward or --renderer=deferred
+    let render_path = deferred::RenderPath::from_args();
+    println!("Renderer: {:?}", render_path);
+    if render_path == deferred::RenderPath::Deferred && anti_aliasing.samples() > 0 {
+        println!("MSAA does not work with the deferred renderer, the G-buffer is drawn without it.");
+    }
+    // --terrain=path.obj, --terrain=heightmap.png or --terrain=random:seed
+    let terrain_source = heightmap::TerrainSource::from_args("./resources/lunarsurface.obj");
+
     let cb = glutin::ContextBuilder::new()
-        .with_vsync(true);
+        .with_vsync(true)
+        .with_multisampling(anti_aliasing.samples());
     let windowed_context = cb.build_windowed(wb, &el).unwrap();
     // Uncomment these if you want to use the mouse for controls, but want it to be confined to the screen and/or invisible.
     // windowed_context.window().set_cursor_grab(true).expect("failed to grab cursor");
@@ -139,6 +323,13 @@ fn main() {
     // Make a reference of this tuple to send to the render thread
     let mouse_delta = Arc::clone(&arc_mouse_delta);
 
+    // Set up shared tuple for the cursor position in pixels from the top left corner of the window
+    let arc_cursor_position = Arc::new(Mutex::new((0f32, 0f32)));
+    // Where the cursor was when the left mouse button was last clicked, taken by the render thread
+    let arc_mouse_click = Arc::new(Mutex::new(None::<(f32, f32)>));
+    // Make a reference of this to send to the render thread
+    let mouse_click = Arc::clone(&arc_mouse_click);
+
     // Spawn a separate thread for rendering, so event handling doesn't block rendering
     let render_thread = thread::spawn(move || {
         // Acquire the OpenGL Context and load the function pointers. This has to be done inside of the rendering thread, because
@@ -154,7 +345,11 @@ fn main() {
             gl::Enable(gl::DEPTH_TEST);
             gl::DepthFunc(gl::LESS);
             gl::Enable(gl::CULL_FACE);
-            gl::Disable(gl::MULTISAMPLE);
+            if anti_aliasing.samples() > 0 {
+                gl::Enable(gl::MULTISAMPLE);
+            } else {
+                gl::Disable(gl::MULTISAMPLE);
+            }
             gl::Enable(gl::BLEND);
             gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
             gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
@@ -178,10 +373,30 @@ fn main() {
 
         
         /* generating the Terrain mesh */
-        let terrain_mesh: mesh::Mesh = mesh::Terrain::load("./resources/lunarsurface.obj");
+        let terrain_mesh: mesh::Mesh = match &terrain_source {
+            heightmap::TerrainSource::Obj(path) => mesh::Terrain::load(path),
+            heightmap::TerrainSource::Heightmap(path) => {
+                let image = image::open(path).expect("Failed to load heightmap").into_luma();
+                mesh::Terrain::from_heightmap(&image, HEIGHTMAP_SCALE, HEIGHTMAP_HEIGHT, true)
+            }
+            heightmap::TerrainSource::Procedural(seed) => mesh::Terrain::generate(*seed, PROCEDURAL_TERRAIN_SIZE, HEIGHTMAP_SCALE, HEIGHTMAP_HEIGHT, true),
+        };
 
         /*loading all messhes from helicopter*/
         let helicopter: mesh::Helicopter = mesh::Helicopter::load("./resources/helicopter.obj");
+        let helicopter_bvh = mesh::HelicopterBvh::new(&helicopter);
+
+        // for gameplay code asking where the ground is, placed where the terrain node is every frame
+        let mut terrain_sampler = terrain_sampler::TerrainSampler::new(&terrain_mesh);
+        // how far above the ground the origin of the helicopter has to stay for the body not to dip into it
+        let helicopter_clearance = HELICOPTER_GROUND_CLEARANCE - helicopter.body.aabb.min.y;
+
+        // the player helicopter flies by the physics, the keys only move the sticks
+        let mut helicopter_physics = physics::Helicopter::new(glm::vec3(0.0, 9.0, 0.0));
+        let mut helicopter_controls = physics::Controls {
+            collective: helicopter_physics.hover_collective(),
+            ..Default::default()
+        };
         let mut scene_graph_obj = scene_graph::SceneNode::new();
         // == // Set up your VAO here
         
@@ -191,18 +406,43 @@ fn main() {
         let mut zombie_heilcopter4 : std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>> ;
         let mut zombie_heilcopter5 : std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>>;
         let mut helicopter_object : std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>>;
+        let mut terrain_obj: scene_graph::Node;
+        let shader_program: shader::Shader;
+        let shadow_depth_shader: shader::Shader;
+        let mut shadow_map: shadow::ShadowMap;
+        let sky: Option<skybox::Skybox>;
+        let hdr_framebuffer: framebuffer::Framebuffer;
+        let msaa_framebuffer: Option<framebuffer::Framebuffer>;
+        let deferred_renderer: Option<deferred::DeferredRenderer>;
+        let mut ssao: ssao::Ssao;
+        let weighted_blended: transparency::WeightedBlended;
+        let mut post_chain: postprocess::PostChain;
+        let mut terrain_lod: terrain_lod::TerrainLod;
+        let id_buffer: picking::IdBuffer;
+        let mut collisions = collision::CollisionWorld::new();
+        let player_collider: usize;
+        let mut door_player: animation::AnimationPlayer;
+        let mut rotor_player: animation::AnimationPlayer;
+        let mut zombie_players: Vec<animation::AnimationPlayer>;
+        let mut zombie_flight: Vec<animation_state::AnimationMixer>;
+        let mut helicopter_animation: animation_state::StateMachine;
+        let mut tone_mapping = TONE_MAPPING;
+        let window_size = context.window().inner_size();
+        let (window_w, window_h) = (window_size.width as i32, window_size.height as i32);
         unsafe {
             // 
 
-            let mut terrain_obj = object::new_terrain(&mut vao, &terrain_mesh);
+            let (terrain_node, terrain_lod_tree) = object::new_terrain(&mut vao, &terrain_mesh, TERRAIN_LOD_DEPTH, TERRAIN_LOD_RESOLUTION, TERRAIN_LOD_MAX_ERROR);
+            terrain_obj = terrain_node;
+            terrain_lod = terrain_lod_tree;
 
-            helicopter_object = object::new_helicopter(&mut vao, &helicopter);
+            helicopter_object = object::new_helicopter(&mut vao, &helicopter, &helicopter_bvh);
 
-             zombie_heilcopter1 = object::zombie_helicopter(&mut vao, &helicopter);
-             zombie_heilcopter2 = object::zombie_helicopter(&mut vao, &helicopter);
-             zombie_heilcopter3 = object::zombie_helicopter(&mut vao, &helicopter);
-             zombie_heilcopter4 = object::zombie_helicopter(&mut vao, &helicopter);
-             zombie_heilcopter5 = object::zombie_helicopter(&mut vao, &helicopter);
+             zombie_heilcopter1 = object::zombie_helicopter(&mut vao, &helicopter, &helicopter_bvh);
+             zombie_heilcopter2 = object::zombie_helicopter(&mut vao, &helicopter, &helicopter_bvh);
+             zombie_heilcopter3 = object::zombie_helicopter(&mut vao, &helicopter, &helicopter_bvh);
+             zombie_heilcopter4 = object::zombie_helicopter(&mut vao, &helicopter, &helicopter_bvh);
+             zombie_heilcopter5 = object::zombie_helicopter(&mut vao, &helicopter, &helicopter_bvh);
             // Adding helicopter graph tree to scene_graph_obj and the one terrain_mesh object node.
 
             terrain_obj.add_child(&helicopter_object);
@@ -211,8 +451,97 @@ fn main() {
             terrain_obj.add_child(&zombie_heilcopter3);
             terrain_obj.add_child(&zombie_heilcopter4);
             terrain_obj.add_child(&zombie_heilcopter5);
+
+            if std::env::args().any(|arg| arg == "--glass") {
+                let mut glass = object::new_glass(&mut vao, &mesh::Glass::panes(&GLASS_COLORS, 6.0, 4.0, 2.0));
+                glass.position = glm::make_vec3(&GLASS_POSITION);
+                terrain_obj.add_child(&glass);
+            }
             
             scene_graph_obj.add_child(&terrain_obj);
+
+            // the zombies fly on a fixed path above the ground, so only the helicopter is checked against it
+            player_collider = collisions.add(&helicopter_object, true);
+            for zombie in [&zombie_heilcopter1, &zombie_heilcopter2, &zombie_heilcopter3, &zombie_heilcopter4, &zombie_heilcopter5] {
+                collisions.add(zombie, false);
+            }
+
+            // B slides the door of the helicopter open and shut, the rotors spin up at the start
+            door_player = load_clip_player("./resources/animations/door.ron", &mut helicopter_object, animation::PlayMode::Once);
+            // it starts shut, so the first press turns it around to play forwards
+            door_player.playing = false;
+            door_player.speed = -1.0;
+            rotor_player = load_clip_player("./resources/animations/rotor_spin_up.json", &mut helicopter_object, animation::PlayMode::Once);
+
+            // the zombies rattle their doors and twitch
+
+            let zombie_clip = std::rc::Rc::new(animation::AnimationClip::new("lurch")
+                .with_track(animation::Track::new("zombie/door", animation::Property::Rotation, animation::Interpolation::Slerp, vec![
+                    animation::Keyframe::new(0.0, glm::vec3(0.0, 0.0, 0.0)),
+                    animation::Keyframe::new(0.3, glm::vec3(0.0, 0.06, 0.0)),
+                    animation::Keyframe::new(0.5, glm::vec3(0.0, 0.01, 0.0)),
+                    animation::Keyframe::new(0.8, glm::vec3(0.0, 0.05, 0.0)),
+                ]))
+                .with_track(animation::Track::new("zombie", animation::Property::Scale, animation::Interpolation::Step, vec![
+                    animation::Keyframe::new(0.0, glm::vec3(1.0, 1.0, 1.0)),
+                    animation::Keyframe::new(0.6, glm::vec3(1.03, 0.97, 1.03)),
+                    animation::Keyframe::new(0.7, glm::vec3(1.0, 1.0, 1.0)),
+                ]))
+                .with_track(animation::Track::new("zombie/tail", animation::Property::Position, animation::Interpolation::Linear, vec![
+                    animation::Keyframe::new(0.0, glm::vec3(0.0, 0.0, 0.0)),
+                    animation::Keyframe::new(0.8, glm::vec3(0.0, -0.1, 0.0)),
+                ])));
+            // --export-clip=path writes it out, as JSON or RON by the extension
+            if let Some(path) = std::env::args().find_map(|arg| arg.strip_prefix("--export-clip=").map(String::from)) {
+                match clip_file::save(&zombie_clip, &path) {
+                    Ok(()) => println!("Wrote clip {} to {}", zombie_clip.name, path),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            zombie_players = vec![&mut zombie_heilcopter1, &mut zombie_heilcopter2, &mut zombie_heilcopter3, &mut zombie_heilcopter4, &mut zombie_heilcopter5].into_iter()
+                .map(|zombie| animation::AnimationPlayer::bind(zombie_clip.clone(), zombie, animation::PlayMode::PingPong).expect("Failed to bind the zombie clip"))
+                .collect();
+            for (i, player) in zombie_players.iter_mut().enumerate() {
+                // out of step with each other
+                player.time = i as f32 * 0.3;
+                player.speed = 1.0 + i as f32 * 0.1;
+            }
+
+            // the zombies fly their path with the rotors going at cruising speed, both clips blended in full
+            zombie_flight = vec![];
+            for (zombie, offset) in [(&mut zombie_heilcopter1, 4.4), (&mut zombie_heilcopter2, 3.0), (&mut zombie_heilcopter3, 2.0), (&mut zombie_heilcopter4, 1.0), (&mut zombie_heilcopter5, 0.0)] {
+                let path = std::rc::Rc::new(animate::zombie_path("zombie", zombie.position.y, ZOMBIE_PATH_SAMPLES));
+                let mut mixer = animation_state::AnimationMixer::new();
+                let layer = mixer.add(animation::AnimationPlayer::bind(path, &mut *zombie, animation::PlayMode::Loop).expect("Failed to bind the zombie path"), 1.0);
+                mixer.layers[layer].player.time = offset;
+                mixer.add(load_clip_player("./resources/animations/cruise.ron", zombie, animation::PlayMode::Loop), 1.0);
+                zombie_flight.push(mixer);
+            }
+
+            // the rotors of the helicopter follow what it is doing, cross-fading from one state to the next. The loops are
+            // synced, so the rotors speed up and slow down through a fade instead of blending two different angles
+            let mut mixer = animation_state::AnimationMixer::new();
+            let layers = ["idle", "takeoff", "cruise", "landing"].map(|state| {
+                let path = format!("./resources/animations/{}.ron", state);
+                (state, mixer.add_synced(load_clip_player(&path, &mut helicopter_object, animation::PlayMode::Loop), 0.0))
+            });
+            helicopter_animation = animation_state::StateMachine::new(mixer);
+            for (state, layer) in layers {
+                helicopter_animation.add_state(state, layer);
+            }
+            {
+                use animation_state::Condition::*;
+                let parameter = String::from;
+                helicopter_animation.add_transition("idle", "takeoff", Above(parameter("lift"), 0.9), 0.8);
+                helicopter_animation.add_transition("takeoff", "cruise", Above(parameter("height"), 4.0), 1.5);
+                helicopter_animation.add_transition("takeoff", "idle", All(vec![Below(parameter("lift"), 0.8), Below(parameter("height"), 0.2)]), 1.0);
+                helicopter_animation.add_transition("cruise", "landing", All(vec![Below(parameter("height"), 4.0), Below(parameter("climb"), -0.2)]), 1.0);
+                helicopter_animation.add_transition("landing", "takeoff", Above(parameter("climb"), 0.5), 0.8);
+                helicopter_animation.add_transition("landing", "idle", All(vec![Below(parameter("height"), 0.2), After(0.5)]), 1.5);
+            }
+
+            // the sun, which used to be hard coded in the fragment shader
+            scene_graph_obj.add_light(light::Light::directional(glm::vec3(0.8, -0.5, 0.6), glm::vec3(1.0, 1.0, 1.0), 1.0).casting_shadows());
             
             
             scene_graph_obj.print();
@@ -223,9 +552,39 @@ fn main() {
             // Basic usage of shader helper
             // The code below returns a shader object, which contains the field .program_id
             // The snippet is not enough to do the assignment, and will need to be modified (outside of just using the correct path)
-            let shader_builder = shader::ShaderBuilder::new().attach_file("./shaders/simple.frag").attach_file("./shaders/simple.vert").link();
-
-            let program_id = shader_builder.program_id; // fetching the program id
+            shader_program = shader::ShaderBuilder::new().attach_file("./shaders/simple.frag").attach_file("./shaders/lighting.frag").attach_file("./shaders/simple.vert").link();
+
+            let program_id = shader_program.program_id; // fetching the program id
+
+            // depth only pass from the sun, sampled by the main shader
+            shadow_depth_shader = shader::ShaderBuilder::new().attach_file("./shaders/shadow_depth.frag").attach_file("./shaders/shadow_depth.vert").link();
+            shadow_map = shadow::ShadowMap::new(SHADOW_RESOLUTION, SHADOW_CASCADES, SHADOW_BIAS, SHADOW_PCF_RADIUS, SHADOW_DISTANCE);
+            shadow_map.split_lambda = SHADOW_SPLIT_LAMBDA;
+            shadow_map.blend_width = SHADOW_CASCADE_BLEND;
+
+            sky = load_skybox();
+
+            // the scene is lit into a floating point framebuffer, and tone mapped into the window afterwards
+            hdr_framebuffer = framebuffer::Framebuffer::new(window_w, window_h, &[gl::RGBA16F], true);
+            post_chain = build_post_chain(window_w, window_h);
+
+            // with MSAA the scene is drawn multisampled, and resolved into the hdr framebuffer before post-processing
+            msaa_framebuffer = match anti_aliasing {
+                antialiasing::AntiAliasing::Msaa(samples) if render_path == deferred::RenderPath::Forward => Some(framebuffer::Framebuffer::new_multisampled(window_w, window_h, &[gl::RGBA16F], true, samples as i32)),
+                _ => None,
+            };
+            deferred_renderer = match render_path {
+                deferred::RenderPath::Deferred => Some(deferred::DeferredRenderer::new(window_w, window_h)),
+                deferred::RenderPath::Forward => None,
+            };
+
+            ssao = ssao::Ssao::new(window_w, window_h, SSAO_KERNEL_SIZE, SSAO_RADIUS, SSAO_INTENSITY);
+            weighted_blended = transparency::WeightedBlended::new(window_w, window_h);
+            id_buffer = picking::IdBuffer::new(window_w, window_h);
+
+            if let Some(pass) = post_chain.pass_mut("fxaa") {
+                pass.enabled = anti_aliasing == antialiasing::AntiAliasing::Fxaa;
+            }
 
             gl::UseProgram(program_id);
             
@@ -243,51 +602,144 @@ fn main() {
 
         /* Create a camera struct to handle the camera movements. */
         let mut camera_struct = unsafe{  camera::Camera::new((SCREEN_H as f32)/(SCREEN_W as f32), fov , 1.0, 1000.0, -28.0) };
+
+        let mut scene_lights: Vec<light::WorldLight> = Vec::with_capacity(light::MAX_LIGHTS);
+
+        let clear_color = glm::vec3(
+            texture::srgb_to_linear(CLEAR_COLOR[0]),
+            texture::srgb_to_linear(CLEAR_COLOR[1]),
+            texture::srgb_to_linear(CLEAR_COLOR[2]),
+        );
+        let mut fog = fog::Fog::new(fog::FogMode::Height, clear_color);
+
+        let mut transparency_mode = TRANSPARENCY_MODE;
+
+        // every pass draws through the queue, the stats add up over the frame and P prints them
+        let mut render_queue = render_queue::RenderQueue::new();
+        let mut frame_stats = render_queue::RenderStats::default();
+
+        // clicking picks a node, K switches between ray casting and the id buffer
+        let mut picking_mode = PICKING_MODE;
+        let mut selection = picking::Selection::default();
+
+        // --deterministic takes exactly one step per frame, and H prints a hash of the scene to compare runs with
+        let deterministic = std::env::args().any(|arg| arg == "--deterministic");
+        let mut game_loop = game_loop::GameLoop::new(SIMULATION_RATE, MAX_SIMULATION_STEPS, deterministic);
+        let mut previous_state = unsafe { game_loop::TransformState::capture(&mut scene_graph_obj) };
+
+        // C pauses and resumes, N steps once while paused, - and = slow down and speed up, 0 goes back to normal speed
+        let mut clock = clock::Clock::new();
+
+        // keys held down during the last frame, so toggles only fire once per key press
+        let mut previous_keys: Vec<VirtualKeyCode> = vec![];
         
      
         loop {
             let now = std::time::Instant::now();
-            let elapsed = now.duration_since(first_frame_time).as_secs_f32();
             let delta_time = now.duration_since(last_frame_time).as_secs_f32();
             last_frame_time = now;
-
+            // the camera moves with the wall clock, so pausing or speeding up the simulation leaves it alone
             let new_theta = theta * delta_time; // taking the delta time and multiplying it to the theta
             let new_camera_speed = camera_speed * delta_time;  // taking the delta time and multiplying it to camera_speed. So Frames per second does not play a part in the movement speed.
 
+            // the keys held down this frame
+            let mut held_keys: Vec<VirtualKeyCode> = vec![];
+
             // Handle keyboard input
             if let Ok(keys) = pressed_keys.lock() {
 
+                for key in keys.iter().filter(|key| !previous_keys.contains(key)) {
+                    match key {
+                        VirtualKeyCode::F => fog.toggle(),
+                        VirtualKeyCode::O => ssao.toggle(),
+                        VirtualKeyCode::P => {
+                            println!("Last frame: {} draw calls, {} state changes ({} programs, {} VAOs, {} textures, {} materials), {} nodes culled",
+                                frame_stats.draw_calls, frame_stats.state_changes(), frame_stats.program_switches,
+                                frame_stats.vao_switches, frame_stats.texture_switches, frame_stats.material_switches,
+                                frame_stats.culled_nodes);
+                        },
+                        VirtualKeyCode::H => {
+                            println!("Step {}, scene state {:016x}", game_loop.steps, unsafe { game_loop::state_hash(&scene_graph_obj) });
+                        },
+                        VirtualKeyCode::C => {
+                            clock.toggle_pause();
+                            println!("Clock: {}", if clock.paused { "paused" } else { "running" });
+                        },
+                        VirtualKeyCode::N => clock.request_step(),
+                        VirtualKeyCode::Minus | VirtualKeyCode::Equals | VirtualKeyCode::Key0 => {
+                            let time_scale = match key {
+                                VirtualKeyCode::Minus => clock.time_scale / TIME_SCALE_FACTOR,
+                                VirtualKeyCode::Equals => clock.time_scale * TIME_SCALE_FACTOR,
+                                _ => 1.0,
+                            };
+                            clock.set_time_scale(time_scale);
+                            println!("Time scale: {}", clock.time_scale);
+                        },
+                        VirtualKeyCode::B => {
+                            // turns around halfway if it is still moving
+                            door_player.speed = -door_player.speed;
+                            door_player.playing = true;
+                        },
+                        VirtualKeyCode::M => {
+                            let mode = zombie_players[0].mode.next();
+                            for player in zombie_players.iter_mut() {
+                                player.mode = mode;
+                                player.playing = true;
+                            }
+                            println!("Zombie clip: {:?}", mode);
+                        },
+                        VirtualKeyCode::K => {
+                            picking_mode = picking_mode.next();
+                            println!("Picking: {:?}", picking_mode);
+                        },
+                        VirtualKeyCode::I => {
+                            transparency_mode = transparency_mode.next();
+                            println!("Transparency: {:?}", transparency_mode);
+                        },
+                        VirtualKeyCode::G => {
+                            fog.next_mode();
+                            println!("Fog mode: {:?}", fog.mode);
+                        },
+                        VirtualKeyCode::T => {
+                            tone_mapping = tone_mapping.next();
+                            if let Some(pass) = post_chain.pass_mut("tonemap") {
+                                pass.set("operator", postprocess::Param::Int(tone_mapping.id()));
+                            }
+                            println!("Tone mapping: {:?}", tone_mapping);
+                        },
+                        VirtualKeyCode::LBracket | VirtualKeyCode::RBracket => {
+                            let scale = if *key == VirtualKeyCode::RBracket { 1.25 } else { 0.8 };
+                            if let Some(pass) = post_chain.pass_mut("tonemap") {
+                                let exposure = pass.float("exposure").unwrap_or(EXPOSURE) * scale;
+                                pass.set("exposure", postprocess::Param::Float(exposure));
+                            }
+                        },
+                        VirtualKeyCode::F1 | VirtualKeyCode::F2 | VirtualKeyCode::F3 |
+                        VirtualKeyCode::F4 | VirtualKeyCode::F5 => {
+                            let index = *key as usize - VirtualKeyCode::F1 as usize;
+                            let enabled = post_chain.toggle(POST_PASSES[index]);
+                            println!("{}: {}", POST_PASSES[index], if enabled { "on" } else { "off" });
+                        },
+                        _ => { }
+                    }
+                }
+                previous_keys = keys.clone();
+
                 for key in keys.iter() {
                     // For each key pressed the camera will be affected and new matrices will be calculated. 
 
                     match key {
                         VirtualKeyCode::W => {
-                            unsafe{ 
-                                camera_struct.forward_backward(new_camera_speed);
-                                animate::move_body(&mut helicopter_object, glm::vec3(0.0, 0.0, -camera_speed), delta_time);
-                             }
-
+                            unsafe{ camera_struct.forward_backward(new_camera_speed); }
                         },
                         VirtualKeyCode::S => {
-                            unsafe{
-                                 camera_struct.forward_backward(-new_camera_speed);
-                                 animate::move_body(&mut helicopter_object, glm::vec3(0.0, 0.0, camera_speed), delta_time);
-                                 }
-  
+                            unsafe{ camera_struct.forward_backward(-new_camera_speed); }
                         },
                         VirtualKeyCode::A => {
-                            unsafe{ 
-                                camera_struct.left_right(new_camera_speed);
-                                animate::move_body(&mut helicopter_object, glm::vec3(-camera_speed, 0.0, 0.0), delta_time);
-                             }
-
+                            unsafe{ camera_struct.left_right(new_camera_speed); }
                         },
                         VirtualKeyCode::D => {
-                            unsafe{ 
-                                camera_struct.left_right(-new_camera_speed);
-                                animate::move_body(&mut helicopter_object, glm::vec3(camera_speed, 0.0, 0.0), delta_time);
-                             }
-
+                            unsafe{ camera_struct.left_right(-new_camera_speed); }
                         },
                         VirtualKeyCode::Q => {
                             // When Q is pressed the camera goes up
@@ -321,7 +773,10 @@ fn main() {
                         },
                         _ => { }
                     }
-                } 
+                }
+
+                // the helicopter controls act on every simulation step taken during the frame
+                held_keys = keys.clone();
             }
             // Handle mouse movement. delta contains the x and y movement of the mouse since last frame in pixels
             if let Ok(mut delta) = mouse_delta.lock() {
@@ -333,24 +788,243 @@ fn main() {
 
     
 
-            unsafe {
+            // the simulation catches up with the time since the last frame in fixed steps
+            let timestep = game_loop.timestep;
+            // fast-forward needs more steps per frame, or the cap would quietly hold it back to normal speed
+            game_loop.max_steps = (MAX_SIMULATION_STEPS as f32 * clock.time_scale.max(1.0)).ceil() as u32;
+            game_loop.begin_frame(clock.frame_delta(delta_time, timestep));
+            while game_loop.next_step() {
+                clock.advance(timestep);
+                previous_state = unsafe { game_loop::TransformState::capture(&mut scene_graph_obj) };
 
+                // the cyclic and the pedals spring back to the middle when let go
+                helicopter_controls.cyclic = glm::zero();
+                helicopter_controls.pedal = 0.0;
 
+                for key in held_keys.iter() {
+                    match key {
+                        VirtualKeyCode::W => helicopter_controls.cyclic.y += 1.0,
+                        VirtualKeyCode::S => helicopter_controls.cyclic.y -= 1.0,
+                        VirtualKeyCode::A => helicopter_controls.cyclic.x -= 1.0,
+                        VirtualKeyCode::D => helicopter_controls.cyclic.x += 1.0,
+                        // the collective stays where it was left, space pulls it up and shift lets it down
+                        VirtualKeyCode::Space => {
+                            helicopter_controls.collective = (helicopter_controls.collective + COLLECTIVE_RATE * timestep).min(1.0);
+                        },
+                        VirtualKeyCode::LShift => {
+                            helicopter_controls.collective = (helicopter_controls.collective - COLLECTIVE_RATE * timestep).max(0.0);
+                        },
+                        VirtualKeyCode::Z => {
+                            helicopter_controls.pedal -= 1.0;
+                        },
+                        VirtualKeyCode::X => {
+                            helicopter_controls.pedal += 1.0;
+                        },
+                        _ => { }
+                    }
+                }
 
-                gl::ClearColor(0.163, 0.163, 0.163, 1.0);
-                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+                unsafe {
+                    // Animating the zombie helicopters here
+                    for mixer in zombie_flight.iter_mut() {
+                        mixer.advance(timestep);
+                        mixer.apply();
+                    }
 
-                // Animating the zombie helicopters here
-                animate::animate(&mut zombie_heilcopter5, elapsed, delta_time, 0.0);
-                animate::animate(&mut zombie_heilcopter4, elapsed, delta_time, 1.0);
-                animate::animate(&mut zombie_heilcopter3, elapsed, delta_time, 2.0);
-                animate::animate(&mut zombie_heilcopter2, elapsed, delta_time, 3.0);
-                animate::animate(&mut zombie_heilcopter1, elapsed, delta_time, 4.4);
-                // Done animating the zombie helicopters...
+                    // a clip that has stopped leaves the nodes alone, so the rotors keep spinning after spinning up
+                    for player in zombie_players.iter_mut().chain([&mut door_player]) {
+                        if player.playing {
+                            player.advance(timestep);
+                            player.apply();
+                        }
+                    }
 
-                animate::animate(&mut helicopter_object, elapsed, delta_time, 4.0);
+                    helicopter_physics.step(timestep, &helicopter_controls);
+
+                    // keeps the helicopter from flying into the ground, its position is in the space of the terrain node
+                    let helicopter_world = terrain_sampler.transform * glm::vec4(helicopter_physics.position.x, helicopter_physics.position.y, helicopter_physics.position.z, 1.0);
+                    let mut helicopter_height = f32::MAX;
+                    if let Some(ground) = terrain_sampler.height_at(helicopter_world.x, helicopter_world.z) {
+                        let lifted = glm::inverse(&terrain_sampler.transform) * glm::vec4(helicopter_world.x, ground + helicopter_clearance, helicopter_world.z, 1.0);
+                        helicopter_physics.rest_on(lifted.y);
+                        helicopter_height = helicopter_physics.position.y - lifted.y;
+                    }
+                    helicopter_physics.apply(&mut helicopter_object);
+
+                    let velocity = helicopter_physics.velocity;
+                    helicopter_animation.set("height", helicopter_height);
+                    helicopter_animation.set("climb", velocity.y);
+                    helicopter_animation.set("speed", glm::length(&glm::vec2(velocity.x, velocity.z)));
+                    helicopter_animation.set("lift", helicopter_controls.collective / helicopter_physics.hover_collective());
+                    if let Some(state) = helicopter_animation.update(timestep) {
+                        println!("Helicopter: {}", state);
+                    }
+                    // the spin-up at the start plays over the rotors of the state machine. It ends a whole number of turns
+                    // around at the speed of idle, so the loops are held at their start until then and carry on from there
+                    if rotor_player.playing {
+                        rotor_player.advance(timestep);
+                        rotor_player.apply();
+                        helicopter_animation.mixer.phase = 0.0;
+                    }
+
+                    update_node_transformations(&mut scene_graph_obj, &glm::identity());
+                    terrain_sampler.set_transform(&terrain_obj.current_transformation_matrix);
+
+                    // the helicopter is pushed out of whatever it flew into, the other contacts are only reported
+                    let contacts = collisions.detect(Some(&terrain_sampler)).to_vec();
+                    for contact in &contacts {
+                        if contact.started {
+                            let other = match contact.b {
+                                collision::ContactWith::Collider(b) => format!("{} {}", (*collisions.colliders[b].node).name, b),
+                                collision::ContactWith::Terrain => String::from("the terrain"),
+                            };
+                            println!("{} {} hit {}, {:.2} deep", (*collisions.colliders[contact.a].node).name, contact.a, other, contact.penetration);
+                        }
+                        let push = if contact.a == player_collider {
+                            contact.normal * contact.penetration
+                        } else if contact.b == collision::ContactWith::Collider(player_collider) {
+                            -contact.normal * contact.penetration
+                        } else {
+                            continue;
+                        };
+                        // the position of the helicopter is in the space of the terrain node
+                        helicopter_physics.push(&(glm::mat4_to_mat3(&glm::inverse(&terrain_sampler.transform)) * push));
+                    }
+                }
+            }
+
+            unsafe {
+                // the nodes are drawn between the last two steps, and put back after the frame
+                let current_state = game_loop::TransformState::capture(&mut scene_graph_obj);
+                game_loop::TransformState::interpolate(&previous_state, &current_state, game_loop.alpha());
                 update_node_transformations(&mut scene_graph_obj, &glm::identity());
-                draw_scene(&scene_graph_obj, &camera_struct.move_camera_matrix());
+
+                // picks the terrain chunks from the world bounds the update just computed
+                let projection_scale = window_h as f32 / (2.0 * (camera_struct.fov / 2.0).tan());
+                terrain_lod.select(&camera_struct.position(), projection_scale);
+
+                // the lights follow their nodes, so they are gathered after the transformations are updated
+                scene_lights.clear();
+                light::collect_lights(&scene_graph_obj, clock.time(), &mut scene_lights);
+
+                render_queue.reset_stats();
+
+                // shadow pass, drawing the scene from the sun into each cascade of the shadow map
+                if let Some(sun_direction) = light::shadow_caster_direction(&scene_lights) {
+                    shadow_map.fit_to_camera(&sun_direction, &camera_struct);
+                    shadow_map.begin();
+                    shadow_depth_shader.activate();
+                    render_queue.build(&mut scene_graph_obj, &camera_struct.view_matrix(), shadow_depth_shader.program_id, None);
+                    for (i, cascade) in shadow_map.cascades.iter().enumerate() {
+                        shadow_map.begin_cascade(i);
+                        render_queue.draw_all(&cascade.light_space_matrix);
+                    }
+                    shadow_map.end();
+                }
+
+                let view_projection = camera_struct.move_camera_matrix();
+                let frustum = bounds::Frustum::from_matrix(&view_projection);
+
+                // picks whatever is under the cursor at the last click, which lights up until the next one
+                let click = mouse_click.lock().ok().and_then(|mut click| click.take());
+                if let Some((cursor_x, cursor_y)) = click {
+                    let picked = match picking_mode {
+                        picking::PickingMode::Ray => {
+                            let ray = picking::screen_ray(cursor_x, cursor_y, window_w, window_h, &view_projection);
+                            bvh::raycast(&mut scene_graph_obj, &ray, f32::MAX).map(|hit| {
+                                println!("Picked {} at a distance of {:.2}, point [{:.2}, {:.2}, {:.2}]", (*hit.node).name, hit.distance, hit.point.x, hit.point.y, hit.point.z);
+                                hit.node
+                            })
+                        },
+                        picking::PickingMode::IdBuffer => {
+                            render_queue.build(&mut scene_graph_obj, &camera_struct.view_matrix(), id_buffer.shader.program_id, Some(&frustum));
+                            id_buffer.pick(&render_queue, &view_projection, cursor_x, cursor_y)
+                                .inspect(|&node| println!("Picked {}", (*node).name))
+                        },
+                    };
+                    if picked.is_none() {
+                        println!("Nothing picked");
+                    }
+                    selection.select(picked);
+                }
+
+                match &deferred_renderer {
+                    Some(deferred) => {
+                        // geometry pass into the G-buffer, then the lights are added into the hdr framebuffer
+                        deferred.begin_geometry();
+                        render_queue.build(&mut scene_graph_obj, &camera_struct.view_matrix(), deferred.geometry_shader.program_id, Some(&frustum));
+                        render_queue.draw_opaque(&view_projection);
+
+                        if ssao.enabled {
+                            ssao.run(&deferred.gbuffer, 1, &camera_struct);
+                        }
+
+                        hdr_framebuffer.bind();
+                        gl::ClearColor(clear_color.x, clear_color.y, clear_color.z, 1.0);
+                        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+                        upload_lighting(&deferred.directional_shader, &scene_lights, &shadow_map, &ssao, &camera_struct, &sky, &fog);
+                        deferred.light_directional(&view_projection);
+                        upload_lighting(&deferred.volume_shader, &scene_lights, &shadow_map, &ssao, &camera_struct, &sky, &fog);
+                        deferred.light_volumes(&scene_lights, &view_projection);
+
+                        // the sky and the transparent parts are drawn against the depth of the opaque scene
+                        deferred.gbuffer.copy_depth_into(&hdr_framebuffer);
+                    },
+                    None => {
+                        // the forward renderer needs a depth and normal prepass for the ambient occlusion
+                        if ssao.enabled {
+                            ssao.begin_prepass();
+                            render_queue.build(&mut scene_graph_obj, &camera_struct.view_matrix(), ssao.prepass_shader.program_id, Some(&frustum));
+                            render_queue.draw_opaque(&view_projection);
+                            ssao.run(&ssao.prepass, 0, &camera_struct);
+                        }
+
+                        match &msaa_framebuffer {
+                            Some(msaa) => msaa.bind(),
+                            None => hdr_framebuffer.bind(),
+                        }
+                        gl::ClearColor(clear_color.x, clear_color.y, clear_color.z, 1.0);
+                        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+                        upload_lighting(&shader_program, &scene_lights, &shadow_map, &ssao, &camera_struct, &sky, &fog);
+                        render_queue.build(&mut scene_graph_obj, &camera_struct.view_matrix(), shader_program.program_id, Some(&frustum));
+                        render_queue.draw_opaque(&view_projection);
+                    },
+                }
+
+                // the sky goes last, so it only fills the pixels nothing else was drawn to
+                if let Some(sky) = &sky {
+                    sky.draw(&camera_struct);
+                }
+
+                // the transparent nodes go on top of everything else, with depth writes off
+                if transparency_mode == transparency::TransparencyMode::Sorted {
+                    upload_lighting(&shader_program, &scene_lights, &shadow_map, &ssao, &camera_struct, &sky, &fog);
+                    render_queue.build(&mut scene_graph_obj, &camera_struct.view_matrix(), shader_program.program_id, Some(&frustum));
+                    render_queue.draw_transparent(&view_projection);
+                }
+
+                if let Some(msaa) = &msaa_framebuffer {
+                    msaa.resolve_into(&hdr_framebuffer);
+                }
+
+                // weighted blended transparency is not multisampled, so it is added after the resolve
+                if transparency_mode == transparency::TransparencyMode::WeightedBlended {
+                    render_queue.build(&mut scene_graph_obj, &camera_struct.view_matrix(), weighted_blended.accumulate_shader.program_id, Some(&frustum));
+                    if !render_queue.transparent.is_empty() {
+                        weighted_blended.begin(&hdr_framebuffer);
+                        upload_lighting(&weighted_blended.accumulate_shader, &scene_lights, &shadow_map, &ssao, &camera_struct, &sky, &fog);
+                        weighted_blended.accumulate(&mut render_queue, &view_projection);
+                        weighted_blended.composite(&hdr_framebuffer);
+                    }
+                }
+
+                // post-processing, ending with the image in the window
+                post_chain.run(&hdr_framebuffer, window_w, window_h);
+                frame_stats = render_queue.stats;
+
+                current_state.restore();
 
             
                 
@@ -415,6 +1089,16 @@ fn main() {
                     _ => { }
                 }
             },
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                if let Ok(mut cursor) = arc_cursor_position.lock() {
+                    *cursor = (position.x as f32, position.y as f32);
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseInput { state: Pressed, button: MouseButton::Left, .. }, .. } => {
+                if let (Ok(cursor), Ok(mut click)) = (arc_cursor_position.lock(), arc_mouse_click.lock()) {
+                    *click = Some(*cursor);
+                }
+            },
             Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
                 // Accumulate mouse movement
                 if let Ok(mut position) = arc_mouse_delta.lock() {
@@ -1,4 +1,8 @@
 use tobj;
+use crate::bounds;
+use crate::bvh;
+use crate::heightmap;
+use std::rc::Rc;
 
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
     color.iter().cloned().cycle().take(num*4).collect()
@@ -9,21 +13,44 @@ pub struct Mesh {
     pub normals: Vec<f32>,
     pub colors: Vec<f32>,
     pub indices: Vec<u32>,
+    pub uvs: Vec<f32>, // u, v pairs, empty when the mesh has no texture coordinates
     pub index_count: i32,
+    pub aabb: bounds::Aabb,
+    pub bounding_sphere: bounds::BoundingSphere,
 }
 
 impl Mesh {
     pub fn from(mesh: tobj::Mesh, color: [f32; 4]) -> Self {
         let num_verts = mesh.positions.len() / 3;
-        let index_count = mesh.indices.len() as i32;
+        Mesh::new(mesh.positions, mesh.normals, generate_color_vec(color, num_verts), mesh.indices).with_uvs(mesh.texcoords)
+    }
+
+    // A mesh made in code, like the terrain chunks. The bounding volumes are computed from the vertices.
+    pub fn new(vertices: Vec<f32>, normals: Vec<f32>, colors: Vec<f32>, indices: Vec<u32>) -> Self {
+        let index_count = indices.len() as i32;
+        let aabb = bounds::Aabb::from_points(&vertices);
+        let bounding_sphere = bounds::BoundingSphere::from_points(&vertices, &aabb);
         Mesh {
-            vertices: mesh.positions,
-            normals: mesh.normals,
-            indices: mesh.indices,
-            colors: generate_color_vec(color, num_verts),
+            vertices,
+            normals,
+            colors,
+            indices,
+            uvs: vec![],
             index_count,
+            aabb,
+            bounding_sphere,
         }
     }
+
+    pub fn with_uvs(mut self, uvs: Vec<f32>) -> Self {
+        self.uvs = uvs;
+        self
+    }
+
+    // Whether any vertex is see-through, those meshes have to be drawn after the opaque ones.
+    pub fn is_transparent(&self) -> bool {
+        self.colors.iter().skip(3).step_by(4).any(|&alpha| alpha < 1.0)
+    }
 }
 
 pub struct Terrain;
@@ -41,6 +68,45 @@ impl Terrain {
 
         Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
     }
+
+    // A grid with scale world units between the pixels, and height world units between black and white.
+    pub fn from_heightmap(image: &image::GrayImage, scale: f32, height: f32, uvs: bool) -> Mesh {
+        println!("Building terrain from a {}x{} heightmap...", image.width(), image.height());
+        heightmap::HeightField::from_image(image).to_mesh(scale, height, uvs)
+    }
+
+    // A new moon-like map, size x size samples, from the seed.
+    pub fn generate(seed: u32, size: usize, scale: f32, height: f32, uvs: bool) -> Mesh {
+        println!("Generating a {}x{} terrain with seed {}...", size, size, seed);
+        heightmap::Generator::new(seed).generate(size, size).to_mesh(scale, height, uvs)
+    }
+}
+
+// A row of tinted, see-through panes of glass standing one behind the other, to show off the transparency.
+pub struct Glass;
+impl Glass {
+    // Panes of width x height, spacing apart along z, each with both sides facing out so back face culling
+    // keeps one of them. The tints cycle through the colors.
+    pub fn panes(colors: &[[f32; 4]], width: f32, height: f32, spacing: f32) -> Mesh {
+        let (mut vertices, mut normals, mut vertex_colors, mut indices) = (vec![], vec![], vec![], vec![]);
+        let (x, y) = (width / 2.0, height / 2.0);
+        for (i, color) in colors.iter().enumerate() {
+            let z = (i as f32 - (colors.len() - 1) as f32 / 2.0) * spacing;
+            for side in [1.0, -1.0] {
+                let first = (vertices.len() / 3) as u32;
+                vertices.extend_from_slice(&[-x, -y, z, x, -y, z, x, y, z, -x, y, z]);
+                normals.extend_from_slice(&[0.0, 0.0, side].repeat(4));
+                vertex_colors.extend(generate_color_vec(*color, 4));
+                // counter-clockwise seen from the side the normal points to
+                if side > 0.0 {
+                    indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+                } else {
+                    indices.extend_from_slice(&[first, first + 2, first + 1, first, first + 3, first + 2]);
+                }
+            }
+        }
+        Mesh::new(vertices, normals, vertex_colors, indices)
+    }
 }
 
 use std::ops::Index;
@@ -89,4 +155,23 @@ impl Helicopter {
             door:       Mesh::from(door_model.mesh,         [0.1, 0.1, 0.3, 1.0]),
         }
     }
+}
+
+// The ray casting trees of the parts of a helicopter, built once and shared by every helicopter drawing them.
+pub struct HelicopterBvh {
+    pub body: Rc<bvh::Bvh>,
+    pub main_rotor: Rc<bvh::Bvh>,
+    pub tail_rotor: Rc<bvh::Bvh>,
+    pub door: Rc<bvh::Bvh>,
+}
+
+impl HelicopterBvh {
+    pub fn new(helicopter: &Helicopter) -> Self {
+        HelicopterBvh {
+            body:       Rc::new(bvh::Bvh::new(&helicopter.body)),
+            main_rotor: Rc::new(bvh::Bvh::new(&helicopter.main_rotor)),
+            tail_rotor: Rc::new(bvh::Bvh::new(&helicopter.tail_rotor)),
+            door:       Rc::new(bvh::Bvh::new(&helicopter.door)),
+        }
+    }
 }
\ No newline at end of file
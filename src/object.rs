@@ -2,68 +2,122 @@ use crate::VAO;
 use crate::scene_graph;
 use crate::mesh;
 use crate::toolbox;
+use crate::light;
+use crate::terrain_lod;
+use crate::bvh;
+use std::rc::Rc;
 extern crate nalgebra_glm as glm;
 
 
 
-pub unsafe fn new_helicopter(vao : &mut u32, mesh: &mesh::Helicopter) -> std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>> {
+pub unsafe fn new_helicopter(vao : &mut u32, mesh: &mesh::Helicopter, bvh: &mesh::HelicopterBvh) -> std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>> {
     //loading in the helicopter
     
     let mut place_holder_vao = VAO::vertex_array_object( vao, &mesh.body.vertices, &mesh.body.indices, &mesh.body.colors, &mesh.body.normals);
     let mut helicopter_object = scene_graph::SceneNode::from_vao(place_holder_vao, mesh.body.index_count,String::from("body") );
+    helicopter_object.set_bounds(&mesh.body);
+    helicopter_object.set_bvh(bvh.body.clone());
     helicopter_object.position = glm::vec3(0.0, 9.0, 0.0);
 
+    // searchlight under the nose, pointing forwards and down so it sweeps the terrain when the helicopter turns
+    helicopter_object.add_light(light::Light::spot(
+        glm::vec3(0.0, 0.5, -4.0),
+        glm::vec3(0.0, -1.0, -1.5),
+        glm::vec3(1.0, 0.95, 0.8),
+        2.0,
+        80.0,
+        0.25,
+        0.4,
+    ));
+
     place_holder_vao = VAO::vertex_array_object( vao, &mesh.main_rotor.vertices, &mesh.main_rotor.indices, &mesh.main_rotor.colors, &mesh.main_rotor.normals);
     let mut main_rotor = scene_graph::SceneNode::from_vao(place_holder_vao, mesh.main_rotor.index_count, String::from("main"));
     main_rotor.rotation = glm::vec3(0.0, 1.0, 0.0);
+    main_rotor.transparent = mesh.main_rotor.is_transparent();
+    main_rotor.set_bounds(&mesh.main_rotor);
+    main_rotor.set_bvh(bvh.main_rotor.clone());
 
     helicopter_object.add_child(&main_rotor);
 
     place_holder_vao = VAO::vertex_array_object(vao, &mesh.tail_rotor.vertices, &mesh.tail_rotor.indices, &mesh.tail_rotor.colors, &mesh.tail_rotor.normals);
     let mut tail_obj = scene_graph::SceneNode::from_vao(place_holder_vao, mesh.tail_rotor.index_count, String::from("tail"));
     tail_obj.reference_point = glm::vec3(0.35, 2.3, 10.4);
+    tail_obj.set_bounds(&mesh.tail_rotor);
+    tail_obj.set_bvh(bvh.tail_rotor.clone());
     tail_obj.rotation = glm::vec3(1.0, 0.0, 0.0);
 
     helicopter_object.add_child(&tail_obj);
 
     place_holder_vao = VAO::vertex_array_object( vao, &mesh.door.vertices, &mesh.door.indices, &mesh.door.colors, &mesh.door.normals);
-    helicopter_object.add_child(&scene_graph::SceneNode::from_vao(place_holder_vao, mesh.door.index_count, String::from("door")));
+    let mut door = scene_graph::SceneNode::from_vao(place_holder_vao, mesh.door.index_count, String::from("door"));
+    door.set_bounds(&mesh.door);
+    door.set_bvh(bvh.door.clone());
+    helicopter_object.add_child(&door);
 
     helicopter_object
 }
 
-pub unsafe fn zombie_helicopter(vao : &mut u32, mesh: &mesh::Helicopter) -> std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>> {
+pub unsafe fn zombie_helicopter(vao : &mut u32, mesh: &mesh::Helicopter, bvh: &mesh::HelicopterBvh) -> std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>> {
     //loading in the helicopter
     
     let mut place_holder_vao = VAO::vertex_array_object( vao, &mesh.body.vertices, &mesh.body.indices, &mesh.body.colors, &mesh.body.normals);
     let mut helicopter_object = scene_graph::SceneNode::from_vao(place_holder_vao, mesh.body.index_count,String::from("zombie") );
+    helicopter_object.set_bounds(&mesh.body);
+    helicopter_object.set_bvh(bvh.body.clone());
+
+    // red beacon on top of the body
+    helicopter_object.add_light(light::Light::point(glm::vec3(0.0, 3.0, 0.0), glm::vec3(1.0, 0.1, 0.05), 1.5, 20.0).blinking(1.5));
 
     place_holder_vao = VAO::vertex_array_object( vao, &mesh.main_rotor.vertices, &mesh.main_rotor.indices, &mesh.main_rotor.colors, &mesh.main_rotor.normals);
     let mut main_rotor = scene_graph::SceneNode::from_vao(place_holder_vao, mesh.main_rotor.index_count, String::from("main"));
     main_rotor.rotation = glm::vec3(0.0, 1.0, 0.0);
+    main_rotor.transparent = mesh.main_rotor.is_transparent();
+    main_rotor.set_bounds(&mesh.main_rotor);
+    main_rotor.set_bvh(bvh.main_rotor.clone());
 
     helicopter_object.add_child(&main_rotor);
 
     place_holder_vao = VAO::vertex_array_object(vao, &mesh.tail_rotor.vertices, &mesh.tail_rotor.indices, &mesh.tail_rotor.colors, &mesh.tail_rotor.normals);
     let mut tail_obj = scene_graph::SceneNode::from_vao(place_holder_vao, mesh.tail_rotor.index_count, String::from("tail"));
     tail_obj.reference_point = glm::vec3(0.35, 2.3, 10.4);
+    tail_obj.set_bounds(&mesh.tail_rotor);
+    tail_obj.set_bvh(bvh.tail_rotor.clone());
 
 
     helicopter_object.add_child(&tail_obj);
 
     place_holder_vao = VAO::vertex_array_object( vao, &mesh.door.vertices, &mesh.door.indices, &mesh.door.colors, &mesh.door.normals);
-    helicopter_object.add_child(&scene_graph::SceneNode::from_vao(place_holder_vao, mesh.door.index_count, String::from("door")));
+    let mut door = scene_graph::SceneNode::from_vao(place_holder_vao, mesh.door.index_count, String::from("door"));
+    door.set_bounds(&mesh.door);
+    door.set_bvh(bvh.door.clone());
+    helicopter_object.add_child(&door);
 
     helicopter_object
 
 }
 
-pub unsafe fn new_terrain(vao : &mut u32, mesh: &mesh::Mesh) -> std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>>{
-
+// See-through panes, drawn with the other transparent nodes after the opaque ones.
+pub unsafe fn new_glass(vao : &mut u32, mesh: &mesh::Mesh) -> std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>> {
     let place_holder_vao = VAO::vertex_array_object(vao, &mesh.vertices, &mesh.indices, &mesh.colors, &mesh.normals);
+    let mut glass = scene_graph::SceneNode::from_vao(place_holder_vao, mesh.index_count, String::from("glass"));
+    glass.transparent = mesh.is_transparent();
+    glass.set_bounds(mesh);
+    glass.set_bvh(Rc::new(bvh::Bvh::new(mesh)));
+    glass
+}
+
+// The terrain is a group node with the quadtree of chunks under it, see terrain_lod.
+// The TerrainLod has to be kept around to pick the chunks to draw each frame.
+pub unsafe fn new_terrain(vao : &mut u32, mesh: &mesh::Mesh, lod_depth: u32, lod_resolution: u32, lod_max_error: f32) -> (std::mem::ManuallyDrop<std::pin::Pin<std::boxed::Box<scene_graph::SceneNode>>>, terrain_lod::TerrainLod) {
+
+    let lod = terrain_lod::TerrainLod::new(vao, mesh, lod_depth, lod_resolution, lod_max_error);
+
+    let mut terrain = scene_graph::SceneNode::new();
+    terrain.name = String::from("terrain");
+    terrain.add_child(&lod.chunks[lod.root].node);
+
+    (terrain, lod)
 
-    return scene_graph::SceneNode::from_vao(place_holder_vao, mesh.index_count, String::from("terrain"));
-  
 }
 
 
@@ -0,0 +1,119 @@
+extern crate nalgebra_glm as glm;
+use crate::animation;
+use crate::scene_graph;
+
+// What the pilot does with the sticks.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Controls {
+    pub collective: f32,   // 0 to 1, how hard the main rotor pulls
+    pub cyclic: glm::Vec2, // -1 to 1, x tilts the helicopter to the right and y tilts the nose down
+    pub pedal: f32,        // -1 to 1, the tail rotor turning the nose to the right
+}
+
+// A rigid-body model of the player helicopter. The main rotor pulls along the up axis of the body, so tilting it
+// with the cyclic is what makes it fly forwards or sideways. The tail rotor turns it around, and drag and gravity
+// pull on it all the time. Everything is in the space of the parent of the helicopter node.
+pub struct Helicopter {
+    pub position: glm::Vec3,
+    pub velocity: glm::Vec3,
+    pub orientation: glm::Quat,
+    pub angular_velocity: glm::Vec3, // radians per second around the axes of the body
+
+    pub mass: f32,
+    pub inertia: glm::Vec3,  // around the axes of the body
+    pub max_thrust: f32,     // of the main rotor at full collective
+    pub cyclic_torque: f32,  // how hard the cyclic tilts the body
+    pub pedal_torque: f32,   // how hard the tail rotor turns the body
+    pub stability: f32,      // how hard the body rights itself, like the real thing hanging under its rotor
+    pub drag: f32,           // grows with the square of the speed
+    pub angular_drag: f32,
+    pub gravity: f32,
+}
+
+impl Helicopter {
+    pub fn new(position: glm::Vec3) -> Helicopter {
+        Helicopter {
+            position,
+            velocity: glm::zero(),
+            orientation: glm::quat_identity(),
+            angular_velocity: glm::zero(),
+            mass: 1.0,
+            inertia: glm::vec3(1.0, 1.0, 1.0),
+            max_thrust: 2.0 * 9.81,
+            cyclic_torque: 1.5,
+            pedal_torque: 3.0,
+            stability: 4.0,
+            drag: 0.005,
+            angular_drag: 2.5,
+            gravity: 9.81,
+        }
+    }
+
+    // The collective that holds the helicopter still in the air.
+    pub fn hover_collective(&self) -> f32 {
+        (self.mass * self.gravity / self.max_thrust).min(1.0)
+    }
+
+    pub fn up(&self) -> glm::Vec3 {
+        glm::quat_rotate_vec3(&self.orientation, &glm::Vec3::y())
+    }
+
+    // Semi-implicit Euler, the velocities first and then the positions with the new velocities.
+    // Meant to be called with the fixed timestep of the game loop, the model gets stiff with long steps.
+    pub fn step(&mut self, dt: f32, controls: &Controls) {
+        let collective = controls.collective.clamp(0.0, 1.0);
+        let cyclic = glm::clamp(&controls.cyclic, -1.0, 1.0);
+        let pedal = controls.pedal.clamp(-1.0, 1.0);
+
+        let up = self.up();
+        let thrust = up * collective * self.max_thrust;
+        let weight = glm::vec3(0.0, -self.mass * self.gravity, 0.0);
+        let drag = -self.velocity * glm::length(&self.velocity) * self.drag;
+        self.velocity += (thrust + weight + drag) / self.mass * dt;
+        self.position += self.velocity * dt;
+
+        // the nose is along -z and the right side along +x, so tilting them down is turning the other way
+        let control_torque = glm::vec3(-cyclic.y * self.cyclic_torque, -pedal * self.pedal_torque, -cyclic.x * self.cyclic_torque);
+        // turns the up axis back towards the sky, worked out in the parent space and moved into the body
+        let righting = glm::cross(&up, &glm::Vec3::y()) * self.stability;
+        let righting = glm::quat_rotate_vec3(&glm::quat_inverse(&self.orientation), &righting);
+        let torque = control_torque + righting - self.angular_velocity * self.angular_drag;
+        self.angular_velocity += torque.component_div(&self.inertia) * dt;
+
+        // the angular velocity is in the body, so the turn goes on the right
+        let angle = glm::length(&self.angular_velocity) * dt;
+        if angle > 0.0 {
+            let turn = glm::quat_angle_axis(angle, &glm::normalize(&self.angular_velocity));
+            self.orientation = glm::quat_normalize(&(self.orientation * turn));
+        }
+    }
+
+    // Keeps the helicopter from sinking below the given height, and stops it falling any further.
+    pub fn rest_on(&mut self, height: f32) {
+        if self.position.y < height {
+            self.position.y = height;
+            self.velocity.y = self.velocity.y.max(0.0);
+        }
+    }
+
+    // Moves the helicopter out of something it ran into, and takes away the speed it had into it.
+    pub fn push(&mut self, offset: &glm::Vec3) {
+        let length = glm::length(offset);
+        if length <= 0.0 {
+            return;
+        }
+        let normal = offset / length;
+        self.position += offset;
+        let into = glm::dot(&self.velocity, &normal);
+        if into < 0.0 {
+            self.velocity -= normal * into;
+        }
+    }
+
+    // Places the node where the body is. The node rotates around x, then y, then z like update_node_transformations
+    // does it, so the orientation is split into those angles.
+    pub fn apply(&self, node: &mut scene_graph::SceneNode) {
+        node.position = self.position;
+        node.rotation = animation::quat_to_euler(&self.orientation);
+    }
+}
@@ -0,0 +1,106 @@
+extern crate nalgebra_glm as glm;
+use crate::bvh;
+use crate::framebuffer;
+use crate::render_queue;
+use crate::scene_graph;
+use crate::shader;
+
+// how much the picked node glows, added to the emission of its material
+const HIGHLIGHT_EMISSION: f32 = 0.6;
+
+// K switches between them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PickingMode {
+    Ray,      // casts a ray through the bvh of every node, and also gives the point and normal that was hit
+    IdBuffer, // draws the id of every node into a framebuffer and reads the one under the cursor, exact to the pixel
+}
+
+impl PickingMode {
+    pub fn next(self) -> PickingMode {
+        match self {
+            PickingMode::Ray => PickingMode::IdBuffer,
+            PickingMode::IdBuffer => PickingMode::Ray,
+        }
+    }
+}
+
+// The ray from the camera through a pixel of the window, in world space.
+// The cursor is in pixels from the top left corner, like the window events give it.
+pub fn screen_ray(cursor_x: f32, cursor_y: f32, width: i32, height: i32, view_projection_matrix: &glm::Mat4) -> bvh::Ray {
+    let x = 2.0 * cursor_x / width as f32 - 1.0;
+    let y = 1.0 - 2.0 * cursor_y / height as f32;
+    let inverse = glm::inverse(view_projection_matrix);
+    let unproject = |z: f32| {
+        let point = inverse * glm::vec4(x, y, z, 1.0);
+        glm::vec3(point.x, point.y, point.z) / point.w
+    };
+    let near = unproject(-1.0);
+    bvh::Ray::new(near, unproject(1.0) - near)
+}
+
+// Picking on the GPU. Every draw item of the queue is drawn with its index as the color.
+pub struct IdBuffer {
+    pub framebuffer: framebuffer::Framebuffer,
+    pub shader: shader::Shader,
+}
+
+impl IdBuffer {
+    pub unsafe fn new(width: i32, height: i32) -> IdBuffer {
+        IdBuffer {
+            framebuffer: framebuffer::Framebuffer::new(width, height, &[gl::R32UI], true),
+            shader: shader::ShaderBuilder::new().attach_file("./shaders/picking_id.frag").attach_file("./shaders/shadow_depth.vert").link(),
+        }
+    }
+
+    // The node drawn at the pixel, from a queue built with the program of the id shader.
+    // Leaves the window bound.
+    pub unsafe fn pick(&self, queue: &render_queue::RenderQueue, view_projection_matrix: &glm::Mat4, cursor_x: f32, cursor_y: f32) -> Option<*mut scene_graph::SceneNode> {
+        self.framebuffer.bind();
+        let background = [0u32; 4];
+        gl::ClearBufferuiv(gl::COLOR, 0, background.as_ptr());
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+
+        self.shader.activate();
+        let id_location = self.shader.get_uniform_location("object_id");
+        let items: Vec<&render_queue::DrawItem> = queue.opaque.iter().chain(queue.transparent.iter()).collect();
+        for (i, item) in items.iter().enumerate() {
+            gl::BindVertexArray(item.vao_id);
+            gl::Uniform1ui(id_location, i as u32 + 1);
+            gl::UniformMatrix4fv(3, 1, gl::FALSE, (view_projection_matrix * item.world_matrix).as_ptr()); // MVP
+            gl::DrawElements(gl::TRIANGLES, item.index_count, gl::UNSIGNED_INT, std::ptr::null());
+        }
+
+        // the rows of the framebuffer go from the bottom up
+        let x = (cursor_x as i32).clamp(0, self.framebuffer.width - 1);
+        let y = (self.framebuffer.height - 1 - cursor_y as i32).clamp(0, self.framebuffer.height - 1);
+        let mut id: u32 = 0;
+        gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+        gl::ReadPixels(x, y, 1, 1, gl::RED_INTEGER, gl::UNSIGNED_INT, &mut id as *mut u32 as *mut std::os::raw::c_void);
+        framebuffer::bind_default(self.framebuffer.width, self.framebuffer.height);
+
+        match id {
+            0 => None,
+            id => items.get(id as usize - 1).map(|item| item.node),
+        }
+    }
+}
+
+// The picked node, lit up by its material until something else is picked.
+#[derive(Default)]
+pub struct Selection {
+    pub node: Option<*mut scene_graph::SceneNode>,
+    material: render_queue::Material, // what the node looked like before it was highlighted
+}
+
+impl Selection {
+    pub unsafe fn select(&mut self, node: Option<*mut scene_graph::SceneNode>) {
+        if let Some(previous) = self.node {
+            (*previous).material = self.material;
+        }
+        self.node = node;
+        if let Some(node) = node {
+            self.material = (*node).material;
+            (*node).material.params[2] += HIGHLIGHT_EMISSION;
+        }
+    }
+}
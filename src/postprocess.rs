@@ -0,0 +1,207 @@
+use crate::framebuffer;
+use crate::shader;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ToneMapping {
+    Reinhard, // c / (1 + c), gentle but washes out highlights
+    Aces,     // filmic curve fitted to the ACES reference transform
+    Exposure, // 1 - exp(-c), simple photographic response
+}
+
+impl ToneMapping {
+    // Goes through the tone mapping operators, one step each call.
+    pub fn next(self) -> ToneMapping {
+        match self {
+            ToneMapping::Reinhard => ToneMapping::Aces,
+            ToneMapping::Aces => ToneMapping::Exposure,
+            ToneMapping::Exposure => ToneMapping::Reinhard,
+        }
+    }
+
+    // The value of the operator uniform in tonemap.frag
+    pub fn id(self) -> i32 {
+        match self {
+            ToneMapping::Reinhard => 0,
+            ToneMapping::Aces => 1,
+            ToneMapping::Exposure => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Param {
+    Float(f32),
+    Int(i32),
+}
+
+// One full-screen fragment shader in the post-processing chain.
+// Every pass can sample the output of the pass before it as `image`, and the input of the whole chain as `scene`.
+pub struct Pass {
+    pub name: String,
+    pub enabled: bool,
+    pub params: Vec<(String, Param)>,
+    pub mipmapped_input: bool, // generates mipmaps for `image` before the pass runs, used by the bloom
+    textures: Vec<(String, u32, u32)>, // extra inputs as (uniform, target, texture)
+    shader: shader::Shader,
+}
+
+impl Pass {
+    pub unsafe fn new(name: &str, fragment_shader: &str) -> Pass {
+        Pass {
+            name: String::from(name),
+            enabled: true,
+            params: vec![],
+            mipmapped_input: false,
+            textures: vec![],
+            shader: shader::ShaderBuilder::new().attach_file("./shaders/fullscreen.vert").attach_file(fragment_shader).link(),
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: Param) -> Pass {
+        self.set(name, value);
+        self
+    }
+
+    // Binds an extra texture to the pass, like the lookup table of the color grading.
+    pub fn with_texture(mut self, uniform: &str, target: u32, texture: u32) -> Pass {
+        self.textures.push((String::from(uniform), target, texture));
+        self
+    }
+
+    pub fn with_mipmapped_input(mut self) -> Pass {
+        self.mipmapped_input = true;
+        self
+    }
+
+    // Sets a uniform of the pass, adding it if the pass didn't have it yet.
+    pub fn set(&mut self, name: &str, value: Param) {
+        match self.params.iter_mut().find(|(param, _)| param == name) {
+            Some(param) => param.1 = value,
+            None => self.params.push((String::from(name), value)),
+        }
+    }
+
+    pub fn float(&self, name: &str) -> Option<f32> {
+        self.params.iter().find(|(param, _)| param == name).and_then(|(_, value)| match value {
+            Param::Float(v) => Some(*v),
+            Param::Int(_) => None,
+        })
+    }
+
+    unsafe fn draw(&self, image: u32, scene: u32, width: i32, height: i32, vao: u32) {
+        if self.mipmapped_input {
+            gl::BindTexture(gl::TEXTURE_2D, image);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+        }
+
+        self.shader.activate();
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, image);
+        gl::Uniform1i(self.shader.get_uniform_location("image"), 0);
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, scene);
+        gl::Uniform1i(self.shader.get_uniform_location("scene"), 1);
+        gl::Uniform2f(self.shader.get_uniform_location("texel_size"), 1.0 / width as f32, 1.0 / height as f32);
+
+        for (i, (uniform, target, texture)) in self.textures.iter().enumerate() {
+            let unit = 2 + i as u32;
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(*target, *texture);
+            gl::Uniform1i(self.shader.get_uniform_location(uniform), unit as i32);
+        }
+
+        for (name, value) in &self.params {
+            let location = self.shader.get_uniform_location(name);
+            match value {
+                Param::Float(v) => gl::Uniform1f(location, *v),
+                Param::Int(v) => gl::Uniform1i(location, *v),
+            }
+        }
+
+        draw_fullscreen(vao);
+    }
+}
+
+// An ordered list of full-screen passes run after the scene has been drawn.
+// The passes ping-pong between two framebuffers, and the last enabled pass draws straight into the window.
+pub struct PostChain {
+    pub passes: Vec<Pass>,
+    ping: framebuffer::Framebuffer,
+    pong: framebuffer::Framebuffer,
+    vao: u32,
+}
+
+impl PostChain {
+    pub unsafe fn new(width: i32, height: i32) -> PostChain {
+        PostChain {
+            passes: vec![],
+            // floating point, so passes that run before the tone mapping keep the full range
+            ping: framebuffer::Framebuffer::new(width, height, &[gl::RGBA16F], false),
+            pong: framebuffer::Framebuffer::new(width, height, &[gl::RGBA16F], false),
+            vao: fullscreen_vao(),
+        }
+    }
+
+    pub fn add(&mut self, pass: Pass) {
+        self.passes.push(pass);
+    }
+
+    pub fn pass_mut(&mut self, name: &str) -> Option<&mut Pass> {
+        self.passes.iter_mut().find(|pass| pass.name == name)
+    }
+
+    // Turns a pass on or off, returning whether it is on now.
+    pub fn toggle(&mut self, name: &str) -> bool {
+        match self.pass_mut(name) {
+            Some(pass) => {
+                pass.enabled = !pass.enabled;
+                pass.enabled
+            },
+            None => false,
+        }
+    }
+
+    // Runs every enabled pass over the input, and draws the result into the window.
+    pub unsafe fn run(&self, input: &framebuffer::Framebuffer, window_width: i32, window_height: i32) {
+        let enabled: Vec<&Pass> = self.passes.iter().filter(|pass| pass.enabled).collect();
+
+        if enabled.is_empty() {
+            // nothing to do, copy the input straight over
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, input.fbo);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+            gl::BlitFramebuffer(0, 0, input.width, input.height, 0, 0, window_width, window_height, gl::COLOR_BUFFER_BIT, gl::LINEAR);
+            framebuffer::bind_default(window_width, window_height);
+            return;
+        }
+
+        let scene = input.color_textures[0];
+        let mut image = scene;
+        for (i, pass) in enabled.iter().enumerate() {
+            let target = if i % 2 == 0 { &self.ping } else { &self.pong };
+            if i + 1 == enabled.len() {
+                framebuffer::bind_default(window_width, window_height);
+            } else {
+                target.bind();
+            }
+
+            pass.draw(image, scene, input.width, input.height, self.vao);
+            image = target.color_textures[0];
+        }
+    }
+}
+
+// An empty VAO, fullscreen.vert makes the vertices of the triangle from gl_VertexID.
+pub unsafe fn fullscreen_vao() -> u32 {
+    let mut vao_id: u32 = 0;
+    gl::GenVertexArrays(1, &mut vao_id);
+    vao_id
+}
+
+// Draws a single triangle covering the whole viewport, without touching the depth buffer.
+pub unsafe fn draw_fullscreen(vao: u32) {
+    gl::Disable(gl::DEPTH_TEST);
+    gl::BindVertexArray(vao);
+    gl::DrawArrays(gl::TRIANGLES, 0, 3);
+    gl::Enable(gl::DEPTH_TEST);
+}
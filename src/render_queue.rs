@@ -0,0 +1,212 @@
+extern crate nalgebra_glm as glm;
+use crate::bounds;
+use crate::scene_graph;
+
+// the unit a material texture is bound to, above the ones the lighting passes use
+pub const MATERIAL_TEXTURE_UNIT: u32 = 7;
+
+// How a surface reacts to light. The params go to the material_params uniform of lighting.frag.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Material {
+    pub params: [f32; 4], // specular strength, shininess, emission, unused
+    pub texture: u32,     // 0 when the surface only uses its vertex colors
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            params: [0.2, 0.25, 0.0, 0.0],
+            texture: 0,
+        }
+    }
+}
+
+// Everything needed to draw one node, collected before anything is drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct DrawItem {
+    pub vao_id: u32,
+    pub index_count: i32,
+    pub material: Material,
+    pub program: u32,
+    pub world_matrix: glm::Mat4,
+    pub depth: f32, // view space distance along the camera direction
+    pub node: *mut scene_graph::SceneNode, // where the item came from, for picking
+}
+
+impl DrawItem {
+    // Sorts by program first, then texture and mesh, since that is roughly how expensive each switch is.
+    // The lowest bits put the closest items first, so the depth test can throw away more of what is behind them.
+    pub fn sort_key(&self) -> u64 {
+        let depth = self.depth.clamp(0.0, 65535.0) as u64;
+        (self.program as u64 & 0xffff) << 48
+            | (self.material.texture as u64 & 0xffff) << 32
+            | (self.vao_id as u64 & 0xffff) << 16
+            | depth
+    }
+}
+
+// What the queue did during a frame, reset at the start of each.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub program_switches: u32,
+    pub vao_switches: u32,
+    pub texture_switches: u32,
+    pub material_switches: u32,
+    pub culled_nodes: u32, // drawable nodes left out of the last build with a frustum
+}
+
+impl RenderStats {
+    pub fn state_changes(&self) -> u32 {
+        self.program_switches + self.vao_switches + self.texture_switches + self.material_switches
+    }
+}
+
+// Instead of drawing while walking the scene graph, the nodes are gathered into draw items first.
+// The opaque ones are sorted to need as few state changes as possible, and the transparent ones back to front.
+#[derive(Default)]
+pub struct RenderQueue {
+    pub opaque: Vec<DrawItem>,
+    pub transparent: Vec<DrawItem>,
+    pub stats: RenderStats,
+}
+
+impl RenderQueue {
+    pub fn new() -> RenderQueue {
+        RenderQueue::default()
+    }
+
+    // Fills the queue with every drawable node, to be drawn with the given program.
+    // With a frustum, the nodes outside of it are culled before they get into the queue. Passes that see more
+    // than the camera, like the shadow maps, leave it out.
+    // Has to be called after the node transformations have been updated for the frame.
+    // The root is borrowed mutably, the items keep pointers to the nodes that picking may change.
+    pub unsafe fn build(&mut self, root: &mut scene_graph::SceneNode, view_matrix: &glm::Mat4, program: u32, frustum: Option<&bounds::Frustum>) {
+        self.opaque.clear();
+        self.transparent.clear();
+        let mut culled = 0;
+        self.gather(root, view_matrix, program, frustum, &mut culled);
+        if frustum.is_some() {
+            self.stats.culled_nodes = culled;
+        }
+        self.sort();
+    }
+
+    unsafe fn gather(&mut self, node: *mut scene_graph::SceneNode, view_matrix: &glm::Mat4, program: u32, frustum: Option<&bounds::Frustum>, culled: &mut u32) {
+        let root = &*node;
+        if let (Some(frustum), Some(world_bounds)) = (frustum, &root.world_bounds) {
+            // nothing in the subtree can be seen
+            if !frustum.intersects_aabb(world_bounds) {
+                *culled += count_drawable(root);
+                return;
+            }
+        }
+
+        // the sphere is the cheaper test, the box is tighter around long thin meshes like the rotors
+        let visible = match (frustum, &root.world_sphere) {
+            (Some(frustum), Some(sphere)) => frustum.intersects_sphere(sphere)
+                && root.bounding_box.is_none_or(|aabb| frustum.intersects_aabb(&aabb.transformed(&root.current_transformation_matrix))),
+            _ => true,
+        };
+
+        let drawable = root.index_count > -1 && root.visible;
+        if drawable && !visible {
+            *culled += 1;
+        } else if drawable {
+            let origin = view_matrix * root.current_transformation_matrix * glm::vec4(0.0, 0.0, 0.0, 1.0);
+            let item = DrawItem {
+                vao_id: root.vao_id,
+                index_count: root.index_count,
+                material: root.material,
+                program,
+                world_matrix: root.current_transformation_matrix,
+                depth: -origin.z,
+                node,
+            };
+            if root.transparent {
+                self.transparent.push(item);
+            } else {
+                self.opaque.push(item);
+            }
+        }
+
+        // Recurse
+        for &child in &root.children {
+            self.gather(child, view_matrix, program, frustum, culled);
+        }
+    }
+
+    pub fn sort(&mut self) {
+        self.opaque.sort_by_key(|item| item.sort_key());
+        // the farthest first, so every layer blends over the ones behind it
+        self.transparent.sort_by(|a, b| b.depth.partial_cmp(&a.depth).unwrap_or(std::cmp::Ordering::Equal));
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = RenderStats::default();
+    }
+
+    pub unsafe fn draw_opaque(&mut self, view_projection_matrix: &glm::Mat4) {
+        execute(&self.opaque, view_projection_matrix, &mut self.stats);
+    }
+
+    // The transparent items are depth tested against the opaque parts, but don't write depth themselves,
+    // so they never hide each other.
+    pub unsafe fn draw_transparent(&mut self, view_projection_matrix: &glm::Mat4) {
+        gl::DepthMask(gl::FALSE);
+        execute(&self.transparent, view_projection_matrix, &mut self.stats);
+        gl::DepthMask(gl::TRUE);
+    }
+
+    // Both lists, for passes that don't care about transparency, like the shadow maps.
+    pub unsafe fn draw_all(&mut self, view_projection_matrix: &glm::Mat4) {
+        execute(&self.opaque, view_projection_matrix, &mut self.stats);
+        execute(&self.transparent, view_projection_matrix, &mut self.stats);
+    }
+}
+
+unsafe fn count_drawable(root: &scene_graph::SceneNode) -> u32 {
+    let own = if root.index_count > -1 && root.visible { 1 } else { 0 };
+    own + root.children.iter().map(|&child| count_drawable(&*child)).sum::<u32>()
+}
+
+// Draws the items in order, only touching the state that differs from the item before.
+unsafe fn execute(items: &[DrawItem], view_projection_matrix: &glm::Mat4, stats: &mut RenderStats) {
+    let mut program = None;
+    let mut vao = None;
+    let mut texture = None;
+    let mut material_params = None;
+    let mut material_location = -1;
+
+    for item in items {
+        if program != Some(item.program) {
+            gl::UseProgram(item.program);
+            material_location = gl::GetUniformLocation(item.program, "material_params\0".as_ptr() as *const i8);
+            program = Some(item.program);
+            // the uniforms belong to the program, so the material has to be sent again
+            material_params = None;
+            stats.program_switches += 1;
+        }
+        if vao != Some(item.vao_id) {
+            gl::BindVertexArray(item.vao_id);
+            vao = Some(item.vao_id);
+            stats.vao_switches += 1;
+        }
+        if item.material.texture != 0 && texture != Some(item.material.texture) {
+            gl::ActiveTexture(gl::TEXTURE0 + MATERIAL_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D, item.material.texture);
+            texture = Some(item.material.texture);
+            stats.texture_switches += 1;
+        }
+        if material_params != Some(item.material.params) {
+            gl::Uniform4fv(material_location, 1, item.material.params.as_ptr());
+            material_params = Some(item.material.params);
+            stats.material_switches += 1;
+        }
+
+        gl::UniformMatrix4fv(3, 1, gl::FALSE, (view_projection_matrix * item.world_matrix).as_ptr()); // MVP
+        gl::UniformMatrix4fv(4, 1, gl::FALSE, item.world_matrix.as_ptr()); // model matrix
+        gl::DrawElements(gl::TRIANGLES, item.index_count, gl::UNSIGNED_INT, std::ptr::null());
+        stats.draw_calls += 1;
+    }
+}
@@ -2,6 +2,13 @@ extern crate nalgebra_glm as glm;
 
 use std::mem::ManuallyDrop;
 use std::pin::Pin;
+use std::rc::Rc;
+
+use crate::bounds;
+use crate::bvh;
+use crate::light;
+use crate::mesh;
+use crate::render_queue;
 
 // Used to crete an unholy abomination upon which you should not cast your gaze.
 // This ended up being a necessity due to wanting to keep the code written by students as "straight forward" as possible
@@ -10,7 +17,7 @@ use std::pin::Pin;
 // and finally prevents the compiler from dropping it automatically at all (ManuallyDrop). If that sounds like a janky solution, it's because it is.
 // Prettier, Rustier and better solutions were tried numerous times, but were all found wanting of having what I arbitrarily decided to be the required level of
 // simplicity of use.
-type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
+pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
 
 pub struct SceneNode {
 
@@ -24,6 +31,17 @@ pub struct SceneNode {
 
     pub vao_id: u32,
     pub index_count: i32,
+    pub transparent: bool, // drawn after the opaque nodes, sorted back to front
+    pub visible: bool,     // hides the mesh of the node, but not its children
+    pub material: render_queue::Material,
+
+    pub bounding_box: Option<bounds::Aabb>,              // around the mesh, in local space
+    pub bounding_sphere: Option<bounds::BoundingSphere>, // around the mesh, in local space
+    pub world_sphere: Option<bounds::BoundingSphere>,    // the bounding sphere in world space
+    pub world_bounds: Option<bounds::Aabb>,              // around the mesh and all the children, in world space
+    pub bvh: Option<Rc<bvh::Bvh>>,                       // the triangles of the mesh, for ray casts
+
+    pub lights: Vec<light::Light>,
 
     pub children: Vec<*mut SceneNode>,
 }
@@ -38,6 +56,15 @@ impl SceneNode {
             current_transformation_matrix: glm::identity(),
             vao_id: 0,
             index_count: -1,
+            transparent: false,
+            visible: true,
+            material: render_queue::Material::default(),
+            bounding_box: None,
+            bounding_sphere: None,
+            world_sphere: None,
+            world_bounds: None,
+            bvh: None,
+            lights: vec![],
             children: vec![],
             name: String::from("NONE")
         })))
@@ -50,6 +77,15 @@ impl SceneNode {
             reference_point: glm::zero(),
             current_transformation_matrix: glm::identity(),
             vao_id, index_count,
+            transparent: false,
+            visible: true,
+            material: render_queue::Material::default(),
+            bounding_box: None,
+            bounding_sphere: None,
+            world_sphere: None,
+            world_bounds: None,
+            bvh: None,
+            lights: vec![],
             children: vec![],
             name : name
         })))
@@ -57,6 +93,18 @@ impl SceneNode {
     pub fn add_child(&mut self, child: &SceneNode) {
         self.children.push(child as *const SceneNode as *mut SceneNode)
     }
+    // Takes the bounding volumes of the mesh drawn by the node, which lets it be culled.
+    pub fn set_bounds(&mut self, mesh: &mesh::Mesh) {
+        self.bounding_box = Some(mesh.aabb);
+        self.bounding_sphere = Some(mesh.bounding_sphere);
+    }
+    // Lets ray casts hit the node, see bvh::raycast(). Nodes drawing the same mesh can share one.
+    pub fn set_bvh(&mut self, bvh: Rc<bvh::Bvh>) {
+        self.bvh = Some(bvh);
+    }
+    pub fn add_light(&mut self, light: light::Light) {
+        self.lights.push(light)
+    }
     pub fn print(&self) {
         let m = self.current_transformation_matrix;
         let matrix_string = format!(
@@ -76,6 +124,7 @@ impl SceneNode {
     VAO:       {}
     Indices:   {}
     Children:  {}
+    Lights:    {}
     Position:  [{:.2}, {:.2}, {:.2}]
     Rotation:  [{:.2}, {:.2}, {:.2}]
     Reference: [{:.2}, {:.2}, {:.2}]
@@ -84,6 +133,7 @@ impl SceneNode {
             self.vao_id,
             self.index_count,
             self.children.len(),
+            self.lights.len(),
             self.position.x,
             self.position.y,
             self.position.z,
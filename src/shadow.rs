@@ -0,0 +1,213 @@
+extern crate nalgebra_glm as glm;
+use crate::camera;
+use crate::shader;
+use std::ptr;
+
+// Has to match MAX_CASCADES in lighting.frag
+pub const MAX_CASCADES: usize = 4;
+
+// One slice of the camera frustum, with its own orthographic projection from the light.
+pub struct Cascade {
+    pub light_space_matrix: glm::Mat4,
+    pub split_near: f32, // view space distance where the cascade starts
+    pub split_far: f32,  // view space distance where the cascade ends
+    pub radius: f32,     // half the width of the area covered by the cascade, in world units
+}
+
+// A depth-only render target seen from a directional light.
+// The camera frustum is split into cascades, each drawn into its own layer of a texture array, so the
+// shadows close to the camera get far more texels than the ones out towards the far plane.
+// The scene is drawn into it first, and the main shader compares against it to find out what is in shadow.
+pub struct ShadowMap {
+    pub fbo: u32,
+    pub depth_texture: u32,
+    pub resolution: i32,
+    pub bias: f32,          // depth bias to avoid shadow acne, scaled up on surfaces facing away from the light
+    pub pcf_radius: i32,    // samples (2r+1)^2 texels around the lookup, 0 turns filtering off
+    pub distance: f32,      // how far out from the camera shadows are drawn
+    pub split_lambda: f32,  // 0.0 gives evenly spaced splits, 1.0 logarithmic ones
+    pub blend_width: f32,   // fraction of each cascade that fades into the next one, 0.0 turns blending off
+    pub enabled: bool,
+    pub cascades: Vec<Cascade>,
+    saved_viewport: [i32; 4],
+}
+
+impl ShadowMap {
+    pub unsafe fn new(resolution: i32, cascade_count: usize, bias: f32, pcf_radius: i32, distance: f32) -> ShadowMap {
+        if cascade_count == 0 || cascade_count > MAX_CASCADES {
+            panic!("The shadow map needs between 1 and {} cascades, got {}", MAX_CASCADES, cascade_count);
+        }
+
+        let mut depth_texture: u32 = 0;
+        gl::GenTextures(1, &mut depth_texture);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, depth_texture);
+        gl::TexImage3D(
+            gl::TEXTURE_2D_ARRAY, 0, gl::DEPTH_COMPONENT32F as i32,
+            resolution, resolution, cascade_count as i32, 0,
+            gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null()
+        );
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        // everything outside of the map counts as lit
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as i32);
+        let border = [1.0f32, 1.0, 1.0, 1.0];
+        gl::TexParameterfv(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_BORDER_COLOR, border.as_ptr());
+        // lets the shader use a sampler2DArrayShadow, which gives us a free bilinear filtered comparison
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+
+        let mut fbo: u32 = 0;
+        gl::GenFramebuffers(1, &mut fbo);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+        gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, depth_texture, 0, 0);
+        gl::DrawBuffer(gl::NONE);
+        gl::ReadBuffer(gl::NONE);
+
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Shadow map framebuffer is incomplete.");
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        let cascades = (0..cascade_count).map(|_| Cascade {
+            light_space_matrix: glm::identity(),
+            split_near: 0.0,
+            split_far: 0.0,
+            radius: 0.0,
+        }).collect();
+
+        ShadowMap {
+            fbo,
+            depth_texture,
+            resolution,
+            bias,
+            pcf_radius,
+            distance,
+            split_lambda: 0.75,
+            blend_width: 0.1,
+            enabled: true,
+            cascades,
+            saved_viewport: [0; 4],
+        }
+    }
+
+    // Splits the camera frustum into the cascades, and fits an orthographic projection from the light around each of them.
+    pub unsafe fn fit_to_camera(&mut self, light_direction: &glm::Vec3, camera: &camera::Camera) {
+        let far = self.distance.min(camera.z_far);
+        let splits = split_distances(camera.z_near, far, self.cascades.len(), self.split_lambda);
+
+        for (i, cascade) in self.cascades.iter_mut().enumerate() {
+            let split_near = if i == 0 { camera.z_near } else { splits[i - 1] };
+            let split_far = splits[i];
+
+            // overlap the previous cascade so the blend region is covered by both
+            let overlap = (split_far - split_near) * self.blend_width;
+            let (matrix, radius) = fit_light_matrix(light_direction, camera, (split_near - overlap).max(camera.z_near), split_far, self.resolution);
+
+            cascade.light_space_matrix = matrix;
+            cascade.split_near = split_near;
+            cascade.split_far = split_far;
+            cascade.radius = radius;
+        }
+    }
+
+    // Binds the shadow framebuffer, ready for the depth pass. Follow up with begin_cascade() for each cascade.
+    pub unsafe fn begin(&mut self) {
+        gl::GetIntegerv(gl::VIEWPORT, self.saved_viewport.as_mut_ptr());
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        gl::Viewport(0, 0, self.resolution, self.resolution);
+        // pushes the depth a tiny bit away from the light along the slope of the polygons
+        gl::Enable(gl::POLYGON_OFFSET_FILL);
+        gl::PolygonOffset(2.0, 4.0);
+    }
+
+    // Attaches and clears the layer of the given cascade, so the next draws end up in it.
+    pub unsafe fn begin_cascade(&self, index: usize) {
+        gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, self.depth_texture, 0, index as i32);
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+    }
+
+    // Goes back to the framebuffer and viewport that were active before begin().
+    pub unsafe fn end(&self) {
+        gl::Disable(gl::POLYGON_OFFSET_FILL);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        let v = self.saved_viewport;
+        gl::Viewport(v[0], v[1], v[2], v[3]);
+    }
+
+    // Sends the shadow uniforms to the active shader and binds the depth texture to the given texture unit.
+    pub unsafe fn bind(&self, shader: &shader::Shader, texture_unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + texture_unit);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.depth_texture);
+        gl::Uniform1i(shader.get_uniform_location("shadow_map"), texture_unit as i32);
+        gl::Uniform1i(shader.get_uniform_location("cascade_count"), self.cascades.len() as i32);
+        gl::Uniform1f(shader.get_uniform_location("cascade_blend"), self.blend_width);
+        gl::Uniform1i(shader.get_uniform_location("shadow_pcf_radius"), self.pcf_radius);
+        gl::Uniform1i(shader.get_uniform_location("shadows_enabled"), self.enabled as i32);
+
+        let first_radius = self.cascades[0].radius.max(1.0);
+        for (i, cascade) in self.cascades.iter().enumerate() {
+            gl::UniformMatrix4fv(shader.get_uniform_location(&format!("light_space_matrices[{}]", i)), 1, gl::FALSE, cascade.light_space_matrix.as_ptr());
+            gl::Uniform1f(shader.get_uniform_location(&format!("cascade_splits[{}]", i)), cascade.split_far);
+            // the texels of the outer cascades cover more ground, so they need more bias
+            gl::Uniform1f(shader.get_uniform_location(&format!("cascade_bias[{}]", i)), self.bias * cascade.radius / first_radius);
+        }
+    }
+}
+
+// The far distance of each cascade, mixing evenly spaced and logarithmic splits by lambda.
+// The logarithmic splits match the way perspective spreads out the pixels, the even ones keep the far cascades from getting huge.
+pub fn split_distances(z_near: f32, z_far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (1..=count).map(|i| {
+        let fraction = i as f32 / count as f32;
+        let logarithmic = z_near * (z_far / z_near).powf(fraction);
+        let uniform = z_near + (z_far - z_near) * fraction;
+        lambda * logarithmic + (1.0 - lambda) * uniform
+    }).collect()
+}
+
+// The eight corners of the camera frustum between z_near and z_far, in world space.
+pub unsafe fn frustum_corners(camera: &camera::Camera, z_near: f32, z_far: f32) -> [glm::Vec3; 8] {
+    let projection = glm::perspective(camera.aspect, camera.fov, z_near, z_far);
+    let inverse = glm::inverse(&(projection * camera.view_matrix()));
+
+    let mut corners = [glm::Vec3::zeros(); 8];
+    let mut i = 0;
+    for &x in &[-1.0f32, 1.0] {
+        for &y in &[-1.0f32, 1.0] {
+            for &z in &[-1.0f32, 1.0] {
+                let corner = inverse * glm::vec4(x, y, z, 1.0);
+                corners[i] = glm::vec3(corner.x, corner.y, corner.z) / corner.w;
+                i += 1;
+            }
+        }
+    }
+    corners
+}
+
+// Builds the view-projection matrix of a directional light which covers the camera frustum between z_near and z_far.
+// Also returns the radius of the covered area.
+pub unsafe fn fit_light_matrix(light_direction: &glm::Vec3, camera: &camera::Camera, z_near: f32, z_far: f32, resolution: i32) -> (glm::Mat4, f32) {
+    let corners = frustum_corners(camera, z_near, z_far);
+
+    let center = corners.iter().fold(glm::Vec3::zeros(), |sum, c| sum + c) / 8.0;
+    let radius = corners.iter().map(|c| glm::distance(c, &center)).fold(0.0f32, f32::max).ceil();
+
+    let direction = glm::normalize(light_direction);
+    // look_at breaks down when looking straight up or down
+    let up = if direction.y.abs() > 0.99 { glm::vec3(0.0, 0.0, 1.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+
+    // the light sits far behind the frustum, so helicopters above it still cast shadows into it
+    let caster_margin = radius * 2.0;
+    let eye = center - direction * (radius + caster_margin);
+    let mut light_view = glm::look_at(&eye, &center, &up);
+
+    // snap the origin to whole texels
+    let texel_size = (2.0 * radius) / resolution as f32;
+    let origin = light_view * glm::vec4(0.0, 0.0, 0.0, 1.0);
+    let snapped = glm::vec2((origin.x / texel_size).round() * texel_size, (origin.y / texel_size).round() * texel_size);
+    light_view = glm::translation(&glm::vec3(snapped.x - origin.x, snapped.y - origin.y, 0.0)) * light_view;
+
+    let light_projection = glm::ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + caster_margin);
+    (light_projection * light_view, radius)
+}
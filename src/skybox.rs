@@ -0,0 +1,191 @@
+extern crate nalgebra_glm as glm;
+use crate::camera;
+use crate::shader;
+use crate::texture;
+use crate::VAO;
+use std::os::raw::c_void;
+
+// The order OpenGL expects the cube faces in: +X, -X, +Y, -Y, +Z, -Z
+pub const FACE_NAMES: [&str; 6] = ["right", "left", "top", "bottom", "front", "back"];
+
+// A cube around the camera which only follows its rotation, so the sky looks infinitely far away.
+// The cubemap is public so other shaders can use it for reflections.
+pub struct Skybox {
+    pub cubemap: u32,
+    vao: u32,
+    shader: shader::Shader,
+}
+
+impl Skybox {
+    // Loads a cubemap from six images, given in the order of FACE_NAMES.
+    pub unsafe fn from_faces(paths: &[&str], color_space: texture::ColorSpace) -> Result<Skybox, image::ImageError> {
+        if paths.len() != 6 {
+            return Err(texture::parameter_error(format!("A skybox needs exactly six faces, got {}", paths.len())));
+        }
+        let mut faces = Vec::with_capacity(6);
+        for path in paths.iter() {
+            faces.push(image::open(path)?.into_rgb());
+        }
+
+        let cubemap = create_cubemap();
+        for (i, face) in faces.iter().enumerate() {
+            upload_face(i, face, color_space);
+        }
+        Ok(Skybox::with_cubemap(cubemap))
+    }
+
+    // Loads a cubemap from a single equirectangular (latitude/longitude) panorama, resampled into faces of face_size pixels.
+    pub unsafe fn from_equirectangular(path: &str, face_size: u32, color_space: texture::ColorSpace) -> Result<Skybox, image::ImageError> {
+        let panorama = image::open(path)?.into_rgb();
+
+        let cubemap = create_cubemap();
+        for face in 0..6 {
+            upload_face(face, &equirectangular_face(&panorama, face, face_size), color_space);
+        }
+        Ok(Skybox::with_cubemap(cubemap))
+    }
+
+    unsafe fn with_cubemap(cubemap: u32) -> Skybox {
+        gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+
+        let shader = shader::ShaderBuilder::new().attach_file("./shaders/skybox.frag").attach_file("./shaders/skybox.vert").link();
+
+        Skybox {
+            cubemap,
+            vao: cube_vao(),
+            shader,
+        }
+    }
+
+    // Draws the sky behind everything already in the depth buffer. Call it after the opaque parts of the scene.
+    pub unsafe fn draw(&self, camera: &camera::Camera) {
+        self.shader.activate();
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.cubemap);
+        gl::UniformMatrix4fv(3, 1, gl::FALSE, camera.sky_matrix().as_ptr());
+
+        // the shader puts the sky on the far plane, which only passes the depth test with LEQUAL
+        gl::DepthFunc(gl::LEQUAL);
+        gl::DepthMask(gl::FALSE);
+        gl::Disable(gl::CULL_FACE);
+
+        gl::BindVertexArray(self.vao);
+        gl::DrawElements(gl::TRIANGLES, 36, gl::UNSIGNED_INT, std::ptr::null());
+
+        gl::Enable(gl::CULL_FACE);
+        gl::DepthMask(gl::TRUE);
+        gl::DepthFunc(gl::LESS);
+    }
+
+    // Binds the cubemap to the given texture unit, and points the environment_map sampler of the active shader at it.
+    pub unsafe fn bind(&self, shader: &shader::Shader, texture_unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + texture_unit);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.cubemap);
+        gl::Uniform1i(shader.get_uniform_location("environment_map"), texture_unit as i32);
+    }
+}
+
+unsafe fn create_cubemap() -> u32 {
+    let mut cubemap: u32 = 0;
+    gl::GenTextures(1, &mut cubemap);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, cubemap);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+    // no seams between the faces
+    gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+    cubemap
+}
+
+unsafe fn upload_face(face: usize, image: &image::RgbImage, color_space: texture::ColorSpace) {
+    // rows of rgb bytes are not always a multiple of 4 bytes long
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+    gl::TexImage2D(
+        gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as u32, 0, color_space.rgb_format() as i32,
+        image.width() as i32, image.height() as i32, 0,
+        gl::RGB, gl::UNSIGNED_BYTE, image.as_ptr() as *const c_void
+    );
+}
+
+// The direction through pixel (x, y) of a cubemap face, following the OpenGL cubemap layout.
+pub fn face_direction(face: usize, x: u32, y: u32, face_size: u32) -> glm::Vec3 {
+    let a = 2.0 * (x as f32 + 0.5) / face_size as f32 - 1.0;
+    let b = 2.0 * (y as f32 + 0.5) / face_size as f32 - 1.0;
+    let direction = match face {
+        0 => glm::vec3(1.0, -b, -a),
+        1 => glm::vec3(-1.0, -b, a),
+        2 => glm::vec3(a, 1.0, b),
+        3 => glm::vec3(a, -1.0, -b),
+        4 => glm::vec3(a, -b, 1.0),
+        5 => glm::vec3(-a, -b, -1.0),
+        _ => panic!("Invalid cubemap face, try [0,5]"),
+    };
+    glm::normalize(&direction)
+}
+
+// Resamples one cubemap face out of an equirectangular panorama, with bilinear filtering.
+pub fn equirectangular_face(panorama: &image::RgbImage, face: usize, face_size: u32) -> image::RgbImage {
+    let (width, height) = panorama.dimensions();
+
+    image::RgbImage::from_fn(face_size, face_size, |x, y| {
+        let direction = face_direction(face, x, y, face_size);
+
+        // longitude wraps around horizontally, latitude goes from the top of the image to the bottom
+        let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * std::f32::consts::PI);
+        let v = direction.y.clamp(-1.0, 1.0).acos() / std::f32::consts::PI;
+
+        let px = u * width as f32 - 0.5;
+        let py = (v * height as f32 - 0.5).clamp(0.0, height as f32 - 1.0);
+        let x0 = px.floor();
+        let y0 = py.floor();
+        let tx = px - x0;
+        let ty = py - y0;
+
+        let sample = |sx: f32, sy: f32| -> glm::Vec3 {
+            let ix = (sx as i64).rem_euclid(width as i64) as u32;
+            let iy = (sy as u32).min(height - 1);
+            let p = panorama.get_pixel(ix, iy);
+            glm::vec3(p[0] as f32, p[1] as f32, p[2] as f32)
+        };
+
+        let top = glm::lerp(&sample(x0, y0), &sample(x0 + 1.0, y0), tx);
+        let bottom = glm::lerp(&sample(x0, y0 + 1.0), &sample(x0 + 1.0, y0 + 1.0), tx);
+        let color = glm::lerp(&top, &bottom, ty);
+
+        image::Rgb([color.x.round() as u8, color.y.round() as u8, color.z.round() as u8])
+    })
+}
+
+// A unit cube, only positions.
+unsafe fn cube_vao() -> u32 {
+    let vertices: Vec<f32> = vec![
+        -1.0, -1.0, -1.0,
+         1.0, -1.0, -1.0,
+         1.0,  1.0, -1.0,
+        -1.0,  1.0, -1.0,
+        -1.0, -1.0,  1.0,
+         1.0, -1.0,  1.0,
+         1.0,  1.0,  1.0,
+        -1.0,  1.0,  1.0,
+    ];
+    let indices: Vec<u32> = vec![
+        0, 1, 2, 2, 3, 0, // back
+        4, 6, 5, 6, 4, 7, // front
+        0, 3, 7, 7, 4, 0, // left
+        1, 5, 6, 6, 2, 1, // right
+        3, 2, 6, 6, 7, 3, // top
+        0, 4, 5, 5, 1, 0, // bottom
+    ];
+
+    let mut vao_id: u32 = 0;
+    gl::GenVertexArrays(1, &mut vao_id);
+    gl::BindVertexArray(vao_id);
+
+    let mut buffer_id = 0;
+    VAO::buffer(&mut buffer_id, &vertices, gl::ARRAY_BUFFER, 0, 3);
+    VAO::buffer(&mut buffer_id, &indices, gl::ELEMENT_ARRAY_BUFFER, 0, 0);
+
+    vao_id
+}
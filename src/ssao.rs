@@ -0,0 +1,139 @@
+extern crate nalgebra_glm as glm;
+use crate::camera;
+use crate::framebuffer;
+use crate::postprocess;
+use crate::shader;
+use crate::util::Random;
+
+// Has to match MAX_KERNEL_SIZE in ssao.frag
+pub const MAX_KERNEL_SIZE: usize = 64;
+
+// the noise texture is NOISE_SIZE x NOISE_SIZE texels, and the blur covers the same area
+const NOISE_SIZE: i32 = 4;
+
+// Screen-space ambient occlusion. Samples a hemisphere around every pixel in view space, and counts how much of it
+// is buried under the depth buffer. The blurred result darkens the ambient light in lighting.frag.
+// The forward renderer gets its depth and normals from a prepass, the deferred one takes them from the G-buffer.
+pub struct Ssao {
+    pub enabled: bool,
+    pub radius: f32,    // how far around each pixel to look for occluders, in world units
+    pub intensity: f32, // 0.0 has no effect, 1.0 applies the full occlusion
+    pub bias: f32,      // keeps flat surfaces from occluding themselves
+    pub kernel: Vec<glm::Vec3>,
+    pub prepass: framebuffer::Framebuffer,
+    pub prepass_shader: shader::Shader,
+    occlusion: framebuffer::Framebuffer,
+    blurred: framebuffer::Framebuffer,
+    noise_texture: u32,
+    ssao_shader: shader::Shader,
+    blur_shader: shader::Shader,
+    vao: u32,
+}
+
+impl Ssao {
+    pub unsafe fn new(width: i32, height: i32, kernel_size: usize, radius: f32, intensity: f32) -> Ssao {
+        if kernel_size == 0 || kernel_size > MAX_KERNEL_SIZE {
+            panic!("The SSAO kernel needs between 1 and {} samples, got {}", MAX_KERNEL_SIZE, kernel_size);
+        }
+
+        let mut random = Random::new(0x5ca1ab1e);
+        Ssao {
+            enabled: true,
+            radius,
+            intensity,
+            bias: 0.05,
+            kernel: hemisphere_kernel(kernel_size, &mut random),
+            prepass: framebuffer::Framebuffer::new(width, height, &[gl::RGBA16F], true),
+            prepass_shader: shader::ShaderBuilder::new().attach_file("./shaders/normal_prepass.frag").attach_file("./shaders/simple.vert").link(),
+            occlusion: framebuffer::Framebuffer::new(width, height, &[gl::R8], false),
+            blurred: framebuffer::Framebuffer::new(width, height, &[gl::R8], false),
+            noise_texture: noise_texture(&mut random),
+            ssao_shader: shader::ShaderBuilder::new().attach_file("./shaders/fullscreen.vert").attach_file("./shaders/ssao.frag").link(),
+            blur_shader: shader::ShaderBuilder::new().attach_file("./shaders/fullscreen.vert").attach_file("./shaders/ssao_blur.frag").link(),
+            vao: postprocess::fullscreen_vao(),
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        println!("SSAO: {}", if self.enabled { "on" } else { "off" });
+    }
+
+    // Binds and clears the prepass framebuffer, and activates the prepass shader. Draw the scene right after.
+    pub unsafe fn begin_prepass(&self) {
+        self.prepass.bind();
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        self.prepass_shader.activate();
+    }
+
+    // Computes and blurs the occlusion from the depth and the world space normals in color texture normal_index of the source.
+    pub unsafe fn run(&self, source: &framebuffer::Framebuffer, normal_index: usize, camera: &camera::Camera) {
+        // the occlusion is a single channel, there is no alpha to blend with
+        gl::Disable(gl::BLEND);
+
+        self.occlusion.bind();
+        self.ssao_shader.activate();
+        source.bind_depth(0);
+        gl::Uniform1i(self.ssao_shader.get_uniform_location("depth_texture"), 0);
+        source.bind_color(normal_index, 1);
+        gl::Uniform1i(self.ssao_shader.get_uniform_location("normal_texture"), 1);
+        gl::ActiveTexture(gl::TEXTURE2);
+        gl::BindTexture(gl::TEXTURE_2D, self.noise_texture);
+        gl::Uniform1i(self.ssao_shader.get_uniform_location("noise_texture"), 2);
+
+        gl::Uniform1i(self.ssao_shader.get_uniform_location("kernel_size"), self.kernel.len() as i32);
+        for (i, sample) in self.kernel.iter().enumerate() {
+            gl::Uniform3fv(self.ssao_shader.get_uniform_location(&format!("kernel[{}]", i)), 1, sample.as_ptr());
+        }
+        gl::Uniform1f(self.ssao_shader.get_uniform_location("radius"), self.radius);
+        gl::Uniform1f(self.ssao_shader.get_uniform_location("bias"), self.bias);
+        gl::Uniform2f(self.ssao_shader.get_uniform_location("noise_scale"),
+            self.occlusion.width as f32 / NOISE_SIZE as f32, self.occlusion.height as f32 / NOISE_SIZE as f32);
+        gl::UniformMatrix4fv(self.ssao_shader.get_uniform_location("projection"), 1, gl::FALSE, camera.m_perspective.as_ptr());
+        gl::UniformMatrix4fv(self.ssao_shader.get_uniform_location("inverse_projection"), 1, gl::FALSE, glm::inverse(&camera.m_perspective).as_ptr());
+        gl::UniformMatrix4fv(self.ssao_shader.get_uniform_location("view_matrix"), 1, gl::FALSE, camera.view_matrix().as_ptr());
+        postprocess::draw_fullscreen(self.vao);
+
+        self.blurred.bind();
+        self.blur_shader.activate();
+        self.occlusion.bind_color(0, 0);
+        gl::Uniform1i(self.blur_shader.get_uniform_location("image"), 0);
+        gl::Uniform2f(self.blur_shader.get_uniform_location("texel_size"), 1.0 / self.blurred.width as f32, 1.0 / self.blurred.height as f32);
+        gl::Uniform1f(self.blur_shader.get_uniform_location("intensity"), self.intensity);
+        postprocess::draw_fullscreen(self.vao);
+
+        gl::Enable(gl::BLEND);
+    }
+
+    // Binds the blurred occlusion to the given texture unit for a shader that links lighting.frag.
+    // The sampler is pointed at its unit even when SSAO is off, so it never shares a unit with the shadow map.
+    pub unsafe fn bind(&self, shader: &shader::Shader, texture_unit: u32) {
+        self.blurred.bind_color(0, texture_unit);
+        gl::Uniform1i(shader.get_uniform_location("ssao_map"), texture_unit as i32);
+        gl::Uniform1i(shader.get_uniform_location("ssao_enabled"), self.enabled as i32);
+    }
+}
+
+// Sample points in the hemisphere around +z, gathered closer to the center so nearby geometry counts the most.
+pub fn hemisphere_kernel(size: usize, random: &mut Random) -> Vec<glm::Vec3> {
+    (0..size).map(|i| {
+        let direction = glm::normalize(&glm::vec3(random.float() * 2.0 - 1.0, random.float() * 2.0 - 1.0, random.float()));
+        let scale = i as f32 / size as f32;
+        direction * random.float() * glm::lerp_scalar(0.1, 1.0, scale * scale)
+    }).collect()
+}
+
+// Random rotations around the z axis, tiled over the screen.
+unsafe fn noise_texture(random: &mut Random) -> u32 {
+    let data: Vec<f32> = (0..NOISE_SIZE * NOISE_SIZE * 2).map(|_| random.float() * 2.0 - 1.0).collect();
+
+    let mut texture: u32 = 0;
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RG16F as i32, NOISE_SIZE, NOISE_SIZE, 0, gl::RG, gl::FLOAT, data.as_ptr() as *const _);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+    texture
+}
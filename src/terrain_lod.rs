@@ -0,0 +1,278 @@
+extern crate nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::bvh;
+use crate::mesh;
+use crate::scene_graph;
+use crate::VAO;
+
+// One square of the quadtree, drawn by its own scene node.
+pub struct Chunk {
+    pub node: scene_graph::Node,
+    pub error: f32,           // how far the simplified surface may be from the full one, in world units
+    pub children: Vec<usize>, // indices into TerrainLod::chunks, empty for the leaves
+}
+
+// The terrain split into a quadtree of chunks. The leaves hold the full mesh, and every level above holds a
+// simplified copy of the four chunks below it. Each frame the tree is walked from the root, and a chunk is drawn
+// as soon as its error projected onto the screen is small enough, otherwise its children are tried instead.
+// The scene nodes of the chunks form the same tree, so frustum culling can skip whole branches of it.
+pub struct TerrainLod {
+    pub chunks: Vec<Chunk>,
+    pub root: usize,
+    pub max_error: f32, // pixels of screen-space error allowed before a chunk is split into its children
+    pub selected: u32,  // chunks drawn after the last select()
+}
+
+impl TerrainLod {
+    // depth is the number of times the terrain is split, giving 4^depth leaves.
+    // resolution is how many simplification cells go across a chunk above the leaves.
+    pub unsafe fn new(vao: &mut u32, mesh: &mesh::Mesh, depth: u32, resolution: u32, max_error: f32) -> TerrainLod {
+        let cells = 1usize << depth;
+        let size = (mesh.aabb.max.x - mesh.aabb.min.x).max(mesh.aabb.max.z - mesh.aabb.min.z).max(1e-3);
+        let leaf_size = size / cells as f32;
+
+        // sort the triangles into the leaves by their centers
+        let mut leaves: Vec<Vec<usize>> = vec![vec![]; cells * cells];
+        for (triangle, corners) in mesh.indices.chunks_exact(3).enumerate() {
+            let center = corners.iter().fold(glm::Vec3::zeros(), |sum, &i| sum + vertex(mesh, i)) / 3.0;
+            let x = (((center.x - mesh.aabb.min.x) / leaf_size) as usize).min(cells - 1);
+            let z = (((center.z - mesh.aabb.min.z) / leaf_size) as usize).min(cells - 1);
+            leaves[z * cells + x].push(triangle);
+        }
+
+        let mut lod = TerrainLod {
+            chunks: vec![],
+            root: 0,
+            max_error,
+            selected: 0,
+        };
+        let builder = ChunkBuilder { mesh, leaves: &leaves, cells, size, depth, resolution };
+        lod.root = builder.build(&mut lod.chunks, vao, 0, 0, 0);
+        lod
+    }
+
+    // Picks the chunks to draw for a camera at the given position.
+    // projection_scale turns world units at a distance of 1 into pixels, screen_height / (2 * tan(fov / 2)).
+    // Has to be called after the node transformations have been updated, since it uses the world bounds.
+    pub fn select(&mut self, camera_position: &glm::Vec3, projection_scale: f32) {
+        self.selected = 0;
+        self.select_chunk(self.root, camera_position, projection_scale);
+    }
+
+    fn select_chunk(&mut self, index: usize, camera_position: &glm::Vec3, projection_scale: f32) {
+        let chunk = &self.chunks[index];
+        let distance = match chunk.node.world_bounds {
+            Some(bounds) => glm::distance(camera_position, &glm::clamp_vec(camera_position, &bounds.min, &bounds.max)),
+            None => 0.0,
+        };
+        let screen_error = chunk.error * projection_scale / distance.max(1e-3);
+
+        if chunk.children.is_empty() || screen_error <= self.max_error {
+            self.chunks[index].node.visible = true;
+            self.selected += 1;
+            for child in self.chunks[index].children.clone() {
+                self.hide(child);
+            }
+        } else {
+            self.chunks[index].node.visible = false;
+            for child in self.chunks[index].children.clone() {
+                self.select_chunk(child, camera_position, projection_scale);
+            }
+        }
+    }
+
+    fn hide(&mut self, index: usize) {
+        self.chunks[index].node.visible = false;
+        for child in self.chunks[index].children.clone() {
+            self.hide(child);
+        }
+    }
+}
+
+struct ChunkBuilder<'a> {
+    mesh: &'a mesh::Mesh,
+    leaves: &'a [Vec<usize>],
+    cells: usize,
+    size: f32,
+    depth: u32,
+    resolution: u32,
+}
+
+impl<'a> ChunkBuilder<'a> {
+    // Builds the chunk at the given level and position in the quadtree along with everything below it,
+    // and returns its index in chunks.
+    unsafe fn build(&self, chunks: &mut Vec<Chunk>, vao: &mut u32, level: u32, x: usize, z: usize) -> usize {
+        let children: Vec<usize> = if level < self.depth {
+            let mut children = vec![];
+            for (dx, dz) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                children.push(self.build(chunks, vao, level + 1, x * 2 + dx, z * 2 + dz));
+            }
+            children
+        } else {
+            vec![]
+        };
+
+        // the leaf cells covered by this chunk
+        let span = 1usize << (self.depth - level);
+        let mut triangles = vec![];
+        for leaf_z in z * span..(z + 1) * span {
+            for leaf_x in x * span..(x + 1) * span {
+                triangles.extend_from_slice(&self.leaves[leaf_z * self.cells + leaf_x]);
+            }
+        }
+
+        let chunk_size = self.size / (1usize << level) as f32;
+        let cell_size = chunk_size / self.resolution as f32;
+        let (mut vertices, mut normals, mut colors, mut indices, error) = if children.is_empty() {
+            let (vertices, normals, colors, indices) = extract(self.mesh, &triangles);
+            (vertices, normals, colors, indices, 0.0)
+        } else {
+            let (vertices, normals, colors, indices) = simplify(self.mesh, &triangles, cell_size);
+            (vertices, normals, colors, indices, cell_size)
+        };
+        // the neighbours are mostly at most one level coarser, so the skirts reach down past their error
+        add_skirts(&mut vertices, &mut normals, &mut colors, &mut indices, cell_size * 2.0);
+
+        let chunk_mesh = mesh::Mesh::new(vertices, normals, colors, indices);
+        let chunk_vao = VAO::vertex_array_object(vao, &chunk_mesh.vertices, &chunk_mesh.indices, &chunk_mesh.colors, &chunk_mesh.normals);
+        let mut node = scene_graph::SceneNode::from_vao(chunk_vao, chunk_mesh.index_count, format!("terrain_chunk_{}_{}_{}", level, x, z));
+        node.set_bounds(&chunk_mesh);
+        // ray casts go against the full detail, whichever level is drawn
+        if children.is_empty() {
+            node.set_bvh(Rc::new(bvh::Bvh::new(&chunk_mesh)));
+        }
+        for &child in &children {
+            node.add_child(&chunks[child].node);
+        }
+
+        chunks.push(Chunk { node, error, children });
+        chunks.len() - 1
+    }
+}
+
+fn vertex(mesh: &mesh::Mesh, index: u32) -> glm::Vec3 {
+    let i = index as usize * 3;
+    glm::vec3(mesh.vertices[i], mesh.vertices[i + 1], mesh.vertices[i + 2])
+}
+
+type MeshData = (Vec<f32>, Vec<f32>, Vec<f32>, Vec<u32>);
+
+// Copies the given triangles out of the mesh, with only the vertices they use.
+fn extract(mesh: &mesh::Mesh, triangles: &[usize]) -> MeshData {
+    let mut remap: HashMap<u32, u32> = HashMap::new();
+    let (mut vertices, mut normals, mut colors, mut indices) = (vec![], vec![], vec![], vec![]);
+
+    for &triangle in triangles {
+        for &old in &mesh.indices[triangle * 3..triangle * 3 + 3] {
+            let new = *remap.entry(old).or_insert_with(|| {
+                let i = old as usize;
+                vertices.extend_from_slice(&mesh.vertices[i * 3..i * 3 + 3]);
+                normals.extend_from_slice(&mesh.normals[i * 3..i * 3 + 3]);
+                colors.extend_from_slice(&mesh.colors[i * 4..i * 4 + 4]);
+                (vertices.len() / 3 - 1) as u32
+            });
+            indices.push(new);
+        }
+    }
+    (vertices, normals, colors, indices)
+}
+
+// Vertex clustering: every vertex is snapped to the average of all the vertices in the same grid cell,
+// and the triangles that collapse into a line or a point are thrown away.
+fn simplify(mesh: &mesh::Mesh, triangles: &[usize], cell_size: f32) -> MeshData {
+    struct Cluster {
+        position: glm::Vec3,
+        normal: glm::Vec3,
+        color: glm::Vec4,
+        count: f32,
+    }
+
+    let mut cell_to_cluster: HashMap<(i32, i32, i32), usize> = HashMap::new();
+    let mut clusters: Vec<Cluster> = vec![];
+    let mut vertex_to_cluster: HashMap<u32, usize> = HashMap::new();
+
+    for &triangle in triangles {
+        for &index in &mesh.indices[triangle * 3..triangle * 3 + 3] {
+            if vertex_to_cluster.contains_key(&index) {
+                continue;
+            }
+            let position = vertex(mesh, index);
+            let cell = (
+                (position.x / cell_size).floor() as i32,
+                (position.y / cell_size).floor() as i32,
+                (position.z / cell_size).floor() as i32,
+            );
+            let cluster = *cell_to_cluster.entry(cell).or_insert_with(|| {
+                clusters.push(Cluster { position: glm::zero(), normal: glm::zero(), color: glm::zero(), count: 0.0 });
+                clusters.len() - 1
+            });
+
+            let i = index as usize;
+            let c = &mut clusters[cluster];
+            c.position += position;
+            c.normal += glm::vec3(mesh.normals[i * 3], mesh.normals[i * 3 + 1], mesh.normals[i * 3 + 2]);
+            c.color += glm::vec4(mesh.colors[i * 4], mesh.colors[i * 4 + 1], mesh.colors[i * 4 + 2], mesh.colors[i * 4 + 3]);
+            c.count += 1.0;
+            vertex_to_cluster.insert(index, cluster);
+        }
+    }
+
+    let mut indices = vec![];
+    for &triangle in triangles {
+        let corners = &mesh.indices[triangle * 3..triangle * 3 + 3];
+        let (a, b, c) = (vertex_to_cluster[&corners[0]], vertex_to_cluster[&corners[1]], vertex_to_cluster[&corners[2]]);
+        if a != b && b != c && a != c {
+            indices.extend_from_slice(&[a as u32, b as u32, c as u32]);
+        }
+    }
+
+    let (mut vertices, mut normals, mut colors) = (vec![], vec![], vec![]);
+    for cluster in &clusters {
+        let position = cluster.position / cluster.count;
+        let normal = glm::normalize(&cluster.normal);
+        let color = cluster.color / cluster.count;
+        vertices.extend_from_slice(&[position.x, position.y, position.z]);
+        normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+        colors.extend_from_slice(&[color.x, color.y, color.z, color.w]);
+    }
+    (vertices, normals, colors, indices)
+}
+
+// Hangs a strip of wall down from every open edge of the chunk. Where a neighbour is drawn at a different
+// level of detail the edges don't line up, and the wall fills the crack that would show the sky through the ground.
+fn add_skirts(vertices: &mut Vec<f32>, normals: &mut Vec<f32>, colors: &mut Vec<f32>, indices: &mut Vec<u32>, skirt_depth: f32) {
+    // an edge used by only one triangle lies on the border of the chunk
+    let mut edges: HashMap<(u32, u32), (u32, u32, u32)> = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            let entry = edges.entry((a.min(b), a.max(b))).or_insert((a, b, 0));
+            entry.2 += 1;
+        }
+    }
+
+    let mut lowered: HashMap<u32, u32> = HashMap::new();
+    let mut lower = |index: u32, vertices: &mut Vec<f32>, normals: &mut Vec<f32>, colors: &mut Vec<f32>| -> u32 {
+        *lowered.entry(index).or_insert_with(|| {
+            let i = index as usize;
+            vertices.extend_from_slice(&[vertices[i * 3], vertices[i * 3 + 1] - skirt_depth, vertices[i * 3 + 2]]);
+            normals.extend_from_slice(&[normals[i * 3], normals[i * 3 + 1], normals[i * 3 + 2]]);
+            colors.extend_from_slice(&[colors[i * 4], colors[i * 4 + 1], colors[i * 4 + 2], colors[i * 4 + 3]]);
+            (vertices.len() / 3 - 1) as u32
+        })
+    };
+
+    let mut skirts = vec![];
+    for &(a, b, count) in edges.values() {
+        if count != 1 {
+            continue;
+        }
+        let a_low = lower(a, vertices, normals, colors);
+        let b_low = lower(b, vertices, normals, colors);
+        // wound the same way as the triangle the edge came from, so the wall faces out of the chunk
+        skirts.extend_from_slice(&[a, a_low, b, b, a_low, b_low]);
+    }
+    indices.extend(skirts);
+}
@@ -0,0 +1,159 @@
+extern crate nalgebra_glm as glm;
+use crate::mesh;
+
+// Answers how high the ground is, and which way it faces, anywhere on the terrain.
+// The triangles are sorted into a grid over the xz plane, so a query only looks at the few in one cell.
+// The transform is the one of the terrain node. It may move, scale and turn the terrain around the y axis,
+// but not tilt it, since the queries are straight down in world space.
+pub struct TerrainSampler {
+    vertices: Vec<glm::Vec3>,
+    normals: Vec<glm::Vec3>, // empty when the mesh has none, then the faces are used
+    triangles: Vec<[u32; 3]>,
+    grid: Vec<Vec<u32>>, // triangle indices for each cell, row by row along z
+    grid_min: glm::Vec2,
+    cell_size: f32,
+    cells_x: usize,
+    cells_z: usize,
+    pub transform: glm::Mat4,
+    inverse: glm::Mat4,
+}
+
+// Where a query hit the terrain, in the local space of the mesh.
+struct Hit {
+    triangle: usize,
+    weights: glm::Vec3,
+    height: f32,
+}
+
+impl TerrainSampler {
+    pub fn new(mesh: &mesh::Mesh) -> TerrainSampler {
+        let vertices: Vec<glm::Vec3> = mesh.vertices.chunks_exact(3).map(|v| glm::vec3(v[0], v[1], v[2])).collect();
+        let normals = if mesh.normals.len() == mesh.vertices.len() {
+            mesh.normals.chunks_exact(3).map(|n| glm::vec3(n[0], n[1], n[2])).collect()
+        } else {
+            vec![]
+        };
+        let triangles: Vec<[u32; 3]> = mesh.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+
+        // around two triangles per cell on an even grid
+        let size = glm::vec2(mesh.aabb.max.x - mesh.aabb.min.x, mesh.aabb.max.z - mesh.aabb.min.z);
+        let cells_across = ((triangles.len() as f32 / 2.0).sqrt().ceil() as usize).max(1);
+        let cell_size = (size.x.max(size.y) / cells_across as f32).max(1e-3);
+        let cells_x = ((size.x / cell_size).ceil() as usize).max(1);
+        let cells_z = ((size.y / cell_size).ceil() as usize).max(1);
+        let grid_min = glm::vec2(mesh.aabb.min.x, mesh.aabb.min.z);
+
+        let mut sampler = TerrainSampler {
+            vertices,
+            normals,
+            triangles,
+            grid: vec![vec![]; cells_x * cells_z],
+            grid_min,
+            cell_size,
+            cells_x,
+            cells_z,
+            transform: glm::identity(),
+            inverse: glm::identity(),
+        };
+
+        // every triangle goes into all the cells its box in xz touches
+        for (index, triangle) in sampler.triangles.iter().enumerate() {
+            let corners = triangle.map(|i| sampler.vertices[i as usize]);
+            let low = glm::vec2(corners.iter().map(|c| c.x).fold(f32::MAX, f32::min), corners.iter().map(|c| c.z).fold(f32::MAX, f32::min));
+            let high = glm::vec2(corners.iter().map(|c| c.x).fold(f32::MIN, f32::max), corners.iter().map(|c| c.z).fold(f32::MIN, f32::max));
+            let (min_x, min_z) = sampler.cell(&low);
+            let (max_x, max_z) = sampler.cell(&high);
+            for z in min_z..=max_z {
+                for x in min_x..=max_x {
+                    sampler.grid[z * sampler.cells_x + x].push(index as u32);
+                }
+            }
+        }
+        sampler
+    }
+
+    // Follows the terrain node, call it after the node transformations have been updated.
+    pub fn set_transform(&mut self, transform: &glm::Mat4) {
+        self.transform = *transform;
+        self.inverse = glm::inverse(transform);
+    }
+
+    // The height of the ground in world space straight below or above (x, z), None outside the terrain.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let local = self.to_local(x, z);
+        let hit = self.hit(&local)?;
+        let world = self.transform * glm::vec4(local.x, hit.height, local.y, 1.0);
+        Some(world.y)
+    }
+
+    // The normal of the ground in world space at (x, z), blended between the vertex normals like in the shaders.
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<glm::Vec3> {
+        let hit = self.hit(&self.to_local(x, z))?;
+        let [a, b, c] = self.triangles[hit.triangle].map(|i| i as usize);
+        let local = if self.normals.is_empty() {
+            let normal = glm::cross(&(self.vertices[b] - self.vertices[a]), &(self.vertices[c] - self.vertices[a]));
+            // the winding of the faces is not known, but the ground always faces up
+            if normal.y < 0.0 { -normal } else { normal }
+        } else {
+            self.normals[a] * hit.weights.x + self.normals[b] * hit.weights.y + self.normals[c] * hit.weights.z
+        };
+        let world = glm::mat4_to_mat3(&self.inverse).transpose() * local;
+        Some(glm::normalize(&world))
+    }
+
+    fn to_local(&self, x: f32, z: f32) -> glm::Vec2 {
+        let local = self.inverse * glm::vec4(x, 0.0, z, 1.0);
+        glm::vec2(local.x, local.z)
+    }
+
+    fn cell(&self, point: &glm::Vec2) -> (usize, usize) {
+        let cell = (point - self.grid_min) / self.cell_size;
+        (
+            (cell.x.max(0.0) as usize).min(self.cells_x - 1),
+            (cell.y.max(0.0) as usize).min(self.cells_z - 1),
+        )
+    }
+
+    // The highest triangle above the point, if the point is over the terrain at all.
+    fn hit(&self, point: &glm::Vec2) -> Option<Hit> {
+        let outside = point.x < self.grid_min.x || point.y < self.grid_min.y
+            || point.x > self.grid_min.x + self.cells_x as f32 * self.cell_size
+            || point.y > self.grid_min.y + self.cells_z as f32 * self.cell_size;
+        if outside {
+            return None;
+        }
+
+        let (x, z) = self.cell(point);
+        let mut best: Option<Hit> = None;
+        for &index in &self.grid[z * self.cells_x + x] {
+            let [a, b, c] = self.triangles[index as usize].map(|i| self.vertices[i as usize]);
+            let weights = match barycentric(point, &a.xz(), &b.xz(), &c.xz()) {
+                Some(weights) => weights,
+                None => continue,
+            };
+            let height = a.y * weights.x + b.y * weights.y + c.y * weights.z;
+            if best.as_ref().is_none_or(|hit| height > hit.height) {
+                best = Some(Hit { triangle: index as usize, weights, height });
+            }
+        }
+        best
+    }
+}
+
+// The weights of a, b and c that add up to the point, or None when the point is outside the triangle.
+fn barycentric(point: &glm::Vec2, a: &glm::Vec2, b: &glm::Vec2, c: &glm::Vec2) -> Option<glm::Vec3> {
+    let (v0, v1, v2) = (b - a, c - a, point - a);
+    let denominator = v0.x * v1.y - v1.x * v0.y;
+    // a triangle standing on its edge covers no ground
+    if denominator.abs() < 1e-9 {
+        return None;
+    }
+    let u = (v2.x * v1.y - v1.x * v2.y) / denominator;
+    let v = (v0.x * v2.y - v2.x * v0.y) / denominator;
+    let w = 1.0 - u - v;
+    let epsilon = -1e-5;
+    if u < epsilon || v < epsilon || w < epsilon {
+        return None;
+    }
+    Some(glm::vec3(w, u, v))
+}
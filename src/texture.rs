@@ -0,0 +1,89 @@
+use std::os::raw::c_void;
+
+// How the values in an image should be read.
+// Colors painted by hand or photographed are sRGB, and have to be turned linear before lighting them.
+// Data like normal maps, masks and lookup tables are already linear, and must be left alone.
+// The sky is loaded as sRGB, and the color grading lookup table as linear.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    // The internal format for an rgb image in this color space. OpenGL does the sRGB decoding when sampling.
+    pub fn rgb_format(self) -> u32 {
+        match self {
+            ColorSpace::Srgb => gl::SRGB8,
+            ColorSpace::Linear => gl::RGB8,
+        }
+    }
+}
+
+// Decodes one sRGB channel into linear space, for colors picked by hand in the code.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// A 3D lookup table for color grading, read from a horizontal strip of square tiles.
+// A strip of size*size by size pixels holds a size^3 table, where each tile is one step of blue.
+pub unsafe fn load_lut(path: &str) -> Result<u32, image::ImageError> {
+    let strip = image::open(path)?.into_rgb();
+    let size = strip.height();
+    if strip.width() != size * size {
+        return Err(parameter_error(format!("The lookup table in {} should be {}x{} pixels, got {}x{}", path, size * size, size, strip.width(), strip.height())));
+    }
+
+    // reorder the tiles into red, then green, then blue slices
+    let mut data: Vec<u8> = Vec::with_capacity((size * size * size * 3) as usize);
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                data.extend_from_slice(&strip.get_pixel(b * size + r, g).0);
+            }
+        }
+    }
+    Ok(create_lut(size as i32, &data))
+}
+
+// For images that load fine but can't be used the way they are.
+pub fn parameter_error(message: String) -> image::ImageError {
+    image::ImageError::Parameter(image::error::ParameterError::from_kind(image::error::ParameterErrorKind::Generic(message)))
+}
+
+// A lookup table that maps every color to itself, for when there is no grading to load.
+pub unsafe fn identity_lut(size: u32) -> u32 {
+    let mut data: Vec<u8> = Vec::with_capacity((size * size * size * 3) as usize);
+    let step = |i: u32| (i as f32 / (size - 1) as f32 * 255.0).round() as u8;
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                data.extend_from_slice(&[step(r), step(g), step(b)]);
+            }
+        }
+    }
+    create_lut(size as i32, &data)
+}
+
+// The table stays linear, it is applied to colors that are already encoded for the screen.
+unsafe fn create_lut(size: i32, data: &[u8]) -> u32 {
+    let mut texture: u32 = 0;
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_3D, texture);
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+    gl::TexImage3D(
+        gl::TEXTURE_3D, 0, ColorSpace::Linear.rgb_format() as i32,
+        size, size, size, 0,
+        gl::RGB, gl::UNSIGNED_BYTE, data.as_ptr() as *const c_void
+    );
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+    texture
+}
@@ -0,0 +1,75 @@
+extern crate nalgebra_glm as glm;
+use crate::framebuffer;
+use crate::postprocess;
+use crate::render_queue;
+use crate::shader;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TransparencyMode {
+    Sorted,          // drawn back to front after the opaque parts, correct as long as the meshes don't intersect
+    WeightedBlended, // order-independent approximation, no sorting but the layers are only averaged
+}
+
+impl TransparencyMode {
+    pub fn next(self) -> TransparencyMode {
+        match self {
+            TransparencyMode::Sorted => TransparencyMode::WeightedBlended,
+            TransparencyMode::WeightedBlended => TransparencyMode::Sorted,
+        }
+    }
+}
+
+// Weighted blended order-independent transparency (McGuire and Bavoil 2013).
+// The transparent layers are summed into an accumulation texture weighted by depth and alpha, and the
+// product of their transparencies goes into a revealage texture. A full-screen pass then blends the average on top.
+pub struct WeightedBlended {
+    pub accumulate_shader: shader::Shader,
+    buffers: framebuffer::Framebuffer,
+    composite_shader: shader::Shader,
+    vao: u32,
+}
+
+impl WeightedBlended {
+    pub unsafe fn new(width: i32, height: i32) -> WeightedBlended {
+        WeightedBlended {
+            accumulate_shader: shader::ShaderBuilder::new()
+                .attach_file("./shaders/oit_accumulate.frag")
+                .attach_file("./shaders/lighting.frag")
+                .attach_file("./shaders/simple.vert")
+                .link(),
+            buffers: framebuffer::Framebuffer::new(width, height, &[gl::RGBA16F, gl::R16F], true),
+            composite_shader: shader::ShaderBuilder::new().attach_file("./shaders/fullscreen.vert").attach_file("./shaders/oit_composite.frag").link(),
+            vao: postprocess::fullscreen_vao(),
+        }
+    }
+
+    // Takes the depth of the opaque scene, and clears the accumulation to nothing and the revealage to fully revealed.
+    // Upload the lighting to accumulate_shader, then call accumulate().
+    pub unsafe fn begin(&self, scene: &framebuffer::Framebuffer) {
+        scene.copy_depth_into(&self.buffers);
+        let nothing = [0.0f32, 0.0, 0.0, 0.0];
+        let revealed = [1.0f32, 1.0, 1.0, 1.0];
+        gl::ClearBufferfv(gl::COLOR, 0, nothing.as_ptr());
+        gl::ClearBufferfv(gl::COLOR, 1, revealed.as_ptr());
+    }
+
+    // Draws the transparent nodes into the accumulation buffers, in any order.
+    // The queue has to be built with accumulate_shader.
+    pub unsafe fn accumulate(&self, queue: &mut render_queue::RenderQueue, view_projection_matrix: &glm::Mat4) {
+        gl::BlendFunci(0, gl::ONE, gl::ONE);
+        gl::BlendFunci(1, gl::ZERO, gl::ONE_MINUS_SRC_COLOR);
+        queue.draw_transparent(view_projection_matrix);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+    }
+
+    // Blends the transparent layers over the scene, leaving the scene framebuffer bound.
+    pub unsafe fn composite(&self, scene: &framebuffer::Framebuffer) {
+        scene.bind();
+        self.composite_shader.activate();
+        self.buffers.bind_color(0, 0);
+        gl::Uniform1i(self.composite_shader.get_uniform_location("accumulation"), 0);
+        self.buffers.bind_color(1, 1);
+        gl::Uniform1i(self.composite_shader.get_uniform_location("revealage"), 1);
+        postprocess::draw_fullscreen(self.vao);
+    }
+}
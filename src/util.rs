@@ -30,3 +30,22 @@ pub extern "system" fn debug_callback(
     }
 }
 
+// A small seeded xorshift generator, so what is made with it comes out the same on every run,
+// like the SSAO kernel and the generated terrains.
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Random {
+        Random { state: seed.max(1) }
+    }
+
+    // the next number in [0, 1)
+    pub fn float(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1 << 24) as f32
+    }
+}